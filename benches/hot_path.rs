@@ -0,0 +1,222 @@
+//! Benchmarks for the work done on every gateway event.
+//!
+//! None of these should reach the Discord API, the client panics if they do.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use tokio::runtime::Runtime;
+use twilight_model::{
+    channel::{thread::AutoArchiveDuration, Channel, Message},
+    gateway::{
+        event::Event,
+        payload::incoming::{ChannelUpdate, ThreadCreate},
+    },
+    guild::{Member, Permissions},
+    http::interaction::InteractionResponse,
+    id::{
+        marker::{
+            ApplicationMarker, ChannelMarker, GuildMarker, InteractionMarker, MessageMarker,
+            UserMarker,
+        },
+        Id,
+    },
+    user::CurrentUserGuild,
+};
+use xlr::{
+    forums::ParentChannel, reactions::Emoji, AppState, Config, DiscordApi, Error, InnerAppState,
+};
+
+const GUILD: u64 = 1;
+const FORUM: u64 = 2;
+const THREAD: u64 = 3;
+
+struct UnreachableClient;
+
+#[async_trait]
+impl DiscordApi for UnreachableClient {
+    async fn channel(&self, _: Id<ChannelMarker>) -> Result<Channel, Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn message(&self, _: Id<ChannelMarker>, _: Id<MessageMarker>) -> Result<Message, Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn latest_message(&self, _: Id<ChannelMarker>) -> Result<Option<Message>, Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn create_message(&self, _: Id<ChannelMarker>, _: &str) -> Result<Message, Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn trigger_typing(&self, _: Id<ChannelMarker>) -> Result<(), Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn create_reaction(
+        &self,
+        _: Id<ChannelMarker>,
+        _: Id<MessageMarker>,
+        _: &Emoji,
+    ) -> Result<(), Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn delete_own_reaction(
+        &self,
+        _: Id<ChannelMarker>,
+        _: Id<MessageMarker>,
+        _: &Emoji,
+    ) -> Result<(), Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn set_auto_archive_duration(
+        &self,
+        _: Id<ChannelMarker>,
+        _: AutoArchiveDuration,
+    ) -> Result<(), Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn current_user_guilds(
+        &self,
+        _: Option<Id<GuildMarker>>,
+    ) -> Result<Vec<CurrentUserGuild>, Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn guild_channels(&self, _: Id<GuildMarker>) -> Result<Vec<Channel>, Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn guild_member(&self, _: Id<GuildMarker>, _: Id<UserMarker>) -> Result<Member, Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn create_global_command(
+        &self,
+        _: Id<ApplicationMarker>,
+        _: &str,
+        _: &str,
+        _: Option<Permissions>,
+    ) -> Result<(), Error> {
+        unreachable!("benchmark called the API")
+    }
+
+    async fn create_interaction_response(
+        &self,
+        _: Id<ApplicationMarker>,
+        _: Id<InteractionMarker>,
+        _: &str,
+        _: &InteractionResponse,
+    ) -> Result<(), Error> {
+        unreachable!("benchmark called the API")
+    }
+}
+
+/// The whole configuration, so the caller's environment can't change what's measured.
+/// Without retries, handlers run inline rather than in their own task.
+const VARS: &[(&str, &str)] = &[("DISCORD_TOKEN", "benchmark"), ("RETRY_BUDGET", "0")];
+
+/// State with one cached channel that isn't a forum, so handling a thread
+/// in it stops right after the cache lookup.
+fn state() -> AppState {
+    let parent = ParentChannel {
+        guild_id: Some(Id::new(GUILD)),
+        reactable: false,
+        read_only: false,
+        default_reaction: None,
+    };
+    let state = InnerAppState::new(
+        UnreachableClient,
+        Config::from_vars(|name| {
+            VARS.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (*value).to_owned())
+        })
+        .expect("benchmark config should be valid"),
+    )
+    .with_forums([(Id::new(FORUM), parent)]);
+    Arc::new(state)
+}
+
+fn channel(id: u64, kind: u8) -> Channel {
+    serde_json::from_value(serde_json::json!({
+        "id": id.to_string(),
+        "type": kind,
+        "guild_id": GUILD.to_string(),
+        "parent_id": FORUM.to_string(),
+        "name": "benchmark",
+    }))
+    .expect("benchmark channel should deserialize")
+}
+
+fn thread_create() -> Event {
+    // 11 is a public thread
+    Event::ThreadCreate(Box::new(ThreadCreate(channel(THREAD, 11))))
+}
+
+fn channel_update() -> Event {
+    // 0 is a text channel
+    Event::ChannelUpdate(Box::new(ChannelUpdate(channel(FORUM, 0))))
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let state = state();
+    let mut group = c.benchmark_group("handle_event");
+    group.bench_function("thread_create_cache_hit", |b| {
+        b.to_async(&runtime).iter_batched(
+            thread_create,
+            |event| xlr::handle_event(&state, event, None),
+            BatchSize::SmallInput,
+        );
+    });
+    group.bench_function("channel_update", |b| {
+        b.to_async(&runtime).iter_batched(
+            channel_update,
+            |event| xlr::handle_event(&state, event, None),
+            BatchSize::SmallInput,
+        );
+    });
+    group.bench_function("ignored", |b| {
+        b.to_async(&runtime).iter_batched(
+            || Event::GatewayHeartbeatAck,
+            |event| xlr::handle_event(&state, event, None),
+            BatchSize::SmallInput,
+        );
+    });
+    group.finish();
+}
+
+/// Many threads at once, to show contention on the forum cache lock.
+fn bench_concurrent(c: &mut Criterion) {
+    const TASKS: usize = 64;
+    let runtime = Runtime::new().unwrap();
+    let state = state();
+    c.bench_function("concurrent_thread_create", |b| {
+        b.to_async(&runtime).iter(|| {
+            let state = state.clone();
+            async move {
+                let tasks: Vec<_> = (0..TASKS)
+                    .map(|_| {
+                        let state = state.clone();
+                        tokio::spawn(async move {
+                            xlr::handle_event(&state, thread_create(), None).await;
+                        })
+                    })
+                    .collect();
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            }
+        });
+    });
+}
+
+criterion_group!(benches, bench_dispatch, bench_concurrent);
+criterion_main!(benches);
@@ -0,0 +1,239 @@
+//! The Discord API operations XLR uses, behind a trait so the client can be swapped out.
+
+use async_trait::async_trait;
+use twilight_http::Client;
+use twilight_model::{
+    channel::{thread::AutoArchiveDuration, Channel, Message},
+    guild::{Member, Permissions},
+    http::interaction::InteractionResponse,
+    id::{
+        marker::{
+            ApplicationMarker, ChannelMarker, GuildMarker, InteractionMarker, MessageMarker,
+            UserMarker,
+        },
+        Id,
+    },
+    user::CurrentUserGuild,
+};
+
+use crate::{reactions::Emoji, Error};
+
+/// Everything XLR asks of Discord's HTTP API.
+///
+/// [`twilight_http::Client`] is the real implementation. Errors carrying an
+/// HTTP status should report it through [`Error::status`], since callers
+/// treat some statuses, like 404, as expected outcomes.
+#[async_trait]
+pub trait DiscordApi: Send + Sync {
+    async fn channel(&self, channel_id: Id<ChannelMarker>) -> Result<Channel, Error>;
+
+    async fn message(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+    ) -> Result<Message, Error>;
+
+    /// The most recent message in a channel, if it has any.
+    async fn latest_message(&self, channel_id: Id<ChannelMarker>)
+        -> Result<Option<Message>, Error>;
+
+    async fn create_message(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        content: &str,
+    ) -> Result<Message, Error>;
+
+    /// Show the bot as typing in a channel for a few seconds.
+    async fn trigger_typing(&self, channel_id: Id<ChannelMarker>) -> Result<(), Error>;
+
+    async fn create_reaction(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+        emoji: &Emoji,
+    ) -> Result<(), Error>;
+
+    /// Remove a reaction the bot added itself.
+    async fn delete_own_reaction(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+        emoji: &Emoji,
+    ) -> Result<(), Error>;
+
+    async fn set_auto_archive_duration(
+        &self,
+        thread_id: Id<ChannelMarker>,
+        duration: AutoArchiveDuration,
+    ) -> Result<(), Error>;
+
+    /// One page of the guilds the bot is in, starting after `after`.
+    async fn current_user_guilds(
+        &self,
+        after: Option<Id<GuildMarker>>,
+    ) -> Result<Vec<CurrentUserGuild>, Error>;
+
+    async fn guild_channels(&self, guild_id: Id<GuildMarker>) -> Result<Vec<Channel>, Error>;
+
+    async fn guild_member(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<Member, Error>;
+
+    /// Create or overwrite a global slash command with no options.
+    ///
+    /// Without `default_member_permissions`, everyone can use the command.
+    async fn create_global_command(
+        &self,
+        application_id: Id<ApplicationMarker>,
+        name: &str,
+        description: &str,
+        default_member_permissions: Option<Permissions>,
+    ) -> Result<(), Error>;
+
+    async fn create_interaction_response(
+        &self,
+        application_id: Id<ApplicationMarker>,
+        interaction_id: Id<InteractionMarker>,
+        token: &str,
+        response: &InteractionResponse,
+    ) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl DiscordApi for Client {
+    async fn channel(&self, channel_id: Id<ChannelMarker>) -> Result<Channel, Error> {
+        Ok(Self::channel(self, channel_id).await?.model().await?)
+    }
+
+    async fn message(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+    ) -> Result<Message, Error> {
+        Ok(Self::message(self, channel_id, message_id)
+            .await?
+            .model()
+            .await?)
+    }
+
+    async fn latest_message(
+        &self,
+        channel_id: Id<ChannelMarker>,
+    ) -> Result<Option<Message>, Error> {
+        let messages = self
+            .channel_messages(channel_id)
+            .limit(1)?
+            .await?
+            .models()
+            .await?;
+        Ok(messages.into_iter().next())
+    }
+
+    async fn create_message(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        content: &str,
+    ) -> Result<Message, Error> {
+        Ok(Self::create_message(self, channel_id)
+            .content(content)?
+            .await?
+            .model()
+            .await?)
+    }
+
+    async fn trigger_typing(&self, channel_id: Id<ChannelMarker>) -> Result<(), Error> {
+        self.create_typing_trigger(channel_id).await?;
+        Ok(())
+    }
+
+    async fn create_reaction(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+        emoji: &Emoji,
+    ) -> Result<(), Error> {
+        Self::create_reaction(self, channel_id, message_id, &emoji.as_request()).await?;
+        Ok(())
+    }
+
+    async fn delete_own_reaction(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+        emoji: &Emoji,
+    ) -> Result<(), Error> {
+        self.delete_current_user_reaction(channel_id, message_id, &emoji.as_request())
+            .await?;
+        Ok(())
+    }
+
+    async fn set_auto_archive_duration(
+        &self,
+        thread_id: Id<ChannelMarker>,
+        duration: AutoArchiveDuration,
+    ) -> Result<(), Error> {
+        self.update_thread(thread_id)
+            .auto_archive_duration(duration)
+            .await?;
+        Ok(())
+    }
+
+    async fn current_user_guilds(
+        &self,
+        after: Option<Id<GuildMarker>>,
+    ) -> Result<Vec<CurrentUserGuild>, Error> {
+        let mut request = Self::current_user_guilds(self);
+        if let Some(after) = after {
+            request = request.after(after);
+        }
+        Ok(request.await?.models().await?)
+    }
+
+    async fn guild_channels(&self, guild_id: Id<GuildMarker>) -> Result<Vec<Channel>, Error> {
+        Ok(Self::guild_channels(self, guild_id).await?.models().await?)
+    }
+
+    async fn guild_member(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<Member, Error> {
+        Ok(Self::guild_member(self, guild_id, user_id)
+            .await?
+            .model()
+            .await?)
+    }
+
+    async fn create_global_command(
+        &self,
+        application_id: Id<ApplicationMarker>,
+        name: &str,
+        description: &str,
+        default_member_permissions: Option<Permissions>,
+    ) -> Result<(), Error> {
+        let interaction = self.interaction(application_id);
+        let mut request = interaction
+            .create_global_command()
+            .chat_input(name, description)?;
+        if let Some(permissions) = default_member_permissions {
+            request = request.default_member_permissions(permissions);
+        }
+        request.await?;
+        Ok(())
+    }
+
+    async fn create_interaction_response(
+        &self,
+        application_id: Id<ApplicationMarker>,
+        interaction_id: Id<InteractionMarker>,
+        token: &str,
+        response: &InteractionResponse,
+    ) -> Result<(), Error> {
+        self.interaction(application_id)
+            .create_response(interaction_id, token, response)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use ahash::AHashMap;
+use parking_lot::Mutex;
+use tokio::time::Instant;
+use twilight_model::id::{marker::ChannelMarker, Id};
+
+use crate::config::Config;
+
+/// Notices when threads are created in one forum faster than usual, like
+/// during an import, and optionally stops reacting there for a while so the
+/// burst doesn't use up the ratelimit for everyone else.
+#[derive(Debug)]
+pub struct BurstDetector {
+    /// Off if unset
+    threshold: Option<u32>,
+    window: Duration,
+    cooldown: Option<Duration>,
+    forums: Mutex<AHashMap<Id<ChannelMarker>, Window>>,
+}
+
+/// Timed with tokio's clock, so tests can skip to the end of a window.
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    threads: u32,
+    paused_until: Option<Instant>,
+}
+
+impl Window {
+    fn is_stale(&self, now: Instant, length: Duration) -> bool {
+        now.duration_since(self.start) > length
+            && self.paused_until.is_none_or(|until| now >= until)
+    }
+}
+
+impl BurstDetector {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            threshold: config.burst_threshold,
+            window: config.burst_window,
+            cooldown: config.burst_cooldown,
+            forums: Mutex::new(AHashMap::new()),
+        }
+    }
+
+    /// Count a new thread in `forum`, returning `false` if reactions there are paused.
+    pub fn allow(&self, forum: Id<ChannelMarker>) -> bool {
+        let Some(threshold) = self.threshold else {
+            return true;
+        };
+        let now = Instant::now();
+        let mut forums = self.forums.lock();
+        if !forums.contains_key(&forum) {
+            // Forget forums that have been quiet, so this doesn't grow forever
+            forums.retain(|_, window| !window.is_stale(now, self.window));
+        }
+        let window = forums.entry(forum).or_insert(Window {
+            start: now,
+            threads: 0,
+            paused_until: None,
+        });
+        if let Some(until) = window.paused_until {
+            if now < until {
+                return false;
+            }
+            info!(forum = forum.get(), "burst cooldown elapsed, resuming");
+            window.paused_until = None;
+            window.start = now;
+            window.threads = 0;
+        }
+        if now.duration_since(window.start) > self.window {
+            window.start = now;
+            window.threads = 0;
+        }
+        window.threads += 1;
+        if window.threads != threshold + 1 {
+            return true;
+        }
+        let Some(cooldown) = self.cooldown else {
+            warn!(
+                forum = forum.get(),
+                threads = window.threads,
+                window_secs = self.window.as_secs(),
+                "burst of new threads in forum"
+            );
+            return true;
+        };
+        warn!(
+            forum = forum.get(),
+            threads = window.threads,
+            window_secs = self.window.as_secs(),
+            cooldown_secs = cooldown.as_secs(),
+            "burst of new threads in forum, pausing reactions there"
+        );
+        window.paused_until = Some(now + cooldown);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    const FORUM: Id<ChannelMarker> = Id::new(2);
+
+    fn detector(cooldown: &str) -> BurstDetector {
+        BurstDetector::new(&mock::config(&[
+            ("BURST_THRESHOLD", "3"),
+            ("BURST_WINDOW_SECS", "60"),
+            ("BURST_COOLDOWN_SECS", cooldown),
+        ]))
+    }
+
+    /// Count `threads` new threads in [`FORUM`], returning how many were allowed.
+    fn allowed(detector: &BurstDetector, threads: u32) -> u32 {
+        (0..threads).map(|_| u32::from(detector.allow(FORUM))).sum()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn threshold_is_allowed() {
+        let detector = detector("300");
+        assert_eq!(allowed(&detector, 3), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn going_over_the_threshold_pauses_the_forum() {
+        let detector = detector("300");
+        assert_eq!(allowed(&detector, 5), 3);
+        // Other forums carry on as usual
+        assert!(detector.allow(Id::new(5)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn without_a_cooldown_bursts_are_only_logged() {
+        let detector = detector("0");
+        assert_eq!(allowed(&detector, 5), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn threads_at_the_end_of_the_window_still_count() {
+        let detector = detector("300");
+        assert_eq!(allowed(&detector, 3), 3);
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(!detector.allow(FORUM));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn threads_after_the_window_start_a_new_one() {
+        let detector = detector("300");
+        assert_eq!(allowed(&detector, 3), 3);
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert_eq!(allowed(&detector, 3), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forum_resumes_after_the_cooldown() {
+        let detector = detector("300");
+        assert_eq!(allowed(&detector, 4), 3);
+        tokio::time::advance(Duration::from_secs(299)).await;
+        assert!(!detector.allow(FORUM));
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(allowed(&detector, 3), 3);
+    }
+}
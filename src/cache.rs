@@ -0,0 +1,241 @@
+//! A map with an optional cap on its size, evicting the least recently used
+//! entry to make room.
+//!
+//! Entries are kept in an index ordered by when they were last used. Lookups
+//! under a read lock can't update that index, so it's corrected lazily: when
+//! evicting, an entry that was used since it was indexed is moved to the back
+//! instead of being removed.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    hash::Hash,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+
+pub struct LruMap<K, V> {
+    name: &'static str,
+    entries: AHashMap<K, Entry<V>>,
+    /// Every key by the tick it was indexed at, oldest first
+    order: BTreeMap<u64, K>,
+    max_entries: Option<usize>,
+    /// Incremented on every access, so older entries have smaller ticks
+    clock: AtomicU64,
+    /// Whether eviction has been logged yet
+    evicting: bool,
+}
+
+struct Entry<V> {
+    value: V,
+    inserted_at: Instant,
+    last_used: AtomicU64,
+    /// This entry's key in `order`
+    indexed: u64,
+}
+
+impl<K: Clone + Eq + Hash, V> LruMap<K, V> {
+    /// `name` identifies the map in logs.
+    pub fn new(name: &'static str, max_entries: Option<usize>) -> Self {
+        Self {
+            name,
+            entries: AHashMap::new(),
+            order: BTreeMap::new(),
+            max_entries: max_entries.filter(|max| *max > 0),
+            clock: AtomicU64::new(0),
+            evicting: false,
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Look up an entry, marking it as recently used.
+    ///
+    /// This only needs a shared reference, so it works under a read lock.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let entry = self.entries.get(key)?;
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(&entry.value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let tick = self.tick();
+        let entry = self.entries.get_mut(key)?;
+        *entry.last_used.get_mut() = tick;
+        if let Some(key) = self.order.remove(&entry.indexed) {
+            self.order.insert(tick, key);
+        }
+        entry.indexed = tick;
+        Some(&mut entry.value)
+    }
+
+    /// Insert an entry, evicting the least recently used one if the map is full.
+    pub fn insert(&mut self, key: K, value: V) {
+        let full = self
+            .max_entries
+            .is_some_and(|max| self.entries.len() >= max);
+        if full && !self.entries.contains_key(&key) {
+            self.evict_oldest();
+        }
+        let tick = self.tick();
+        let entry = Entry {
+            value,
+            inserted_at: Instant::now(),
+            last_used: AtomicU64::new(tick),
+            indexed: tick,
+        };
+        self.order.insert(tick, key.clone());
+        if let Some(replaced) = self.entries.insert(key, entry) {
+            self.order.remove(&replaced.indexed);
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        if !self.evicting {
+            self.evicting = true;
+            warn!(
+                cache = self.name,
+                max_entries = self.max_entries,
+                "cache is full, evicting least recently used entries"
+            );
+        }
+        while let Some((tick, key)) = self.order.pop_first() {
+            let Some(entry) = self.entries.get_mut(&key) else {
+                continue;
+            };
+            let last_used = *entry.last_used.get_mut();
+            if last_used > tick {
+                // Used under a read lock since it was indexed
+                entry.indexed = last_used;
+                self.order.insert(last_used, key);
+                continue;
+            }
+            self.entries.remove(&key);
+            return;
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.indexed);
+        Some(entry.value)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &V) -> bool) {
+        let order = &mut self.order;
+        self.entries.retain(|key, entry| {
+            let kept = keep(key, &entry.value);
+            if !kept {
+                order.remove(&entry.indexed);
+            }
+            kept
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Every entry along with how long ago it was inserted, without marking any as used.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V, Duration)> {
+        self.entries
+            .iter()
+            .map(|(key, entry)| (key, &entry.value, entry.inserted_at.elapsed()))
+    }
+}
+
+impl<K: Clone + Eq + Hash, V> Extend<(K, V)> for LruMap<K, V> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: fmt::Debug + Eq + Hash, V: fmt::Debug> fmt::Debug for LruMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.entries.iter().map(|(key, entry)| (key, &entry.value)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(map: &LruMap<u32, ()>) -> Vec<u32> {
+        let mut keys: Vec<_> = map.iter().map(|(key, ..)| *key).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn evicts_least_recently_inserted() {
+        let mut map = LruMap::new("test", Some(2));
+        map.insert(1, ());
+        map.insert(2, ());
+        map.insert(3, ());
+        assert_eq!(keys(&map), [2, 3]);
+    }
+
+    #[test]
+    fn reads_keep_entries_around() {
+        let mut map = LruMap::new("test", Some(2));
+        map.insert(1, ());
+        map.insert(2, ());
+        map.get(&1);
+        map.insert(3, ());
+        assert_eq!(keys(&map), [1, 3]);
+        // 1 was moved back in the index when it was passed over
+        map.insert(4, ());
+        assert_eq!(keys(&map), [3, 4]);
+    }
+
+    #[test]
+    fn writes_keep_entries_around() {
+        let mut map = LruMap::new("test", Some(2));
+        map.insert(1, ());
+        map.insert(2, ());
+        map.get_mut(&1);
+        map.insert(3, ());
+        assert_eq!(keys(&map), [1, 3]);
+    }
+
+    #[test]
+    fn reinserting_refreshes_an_entry() {
+        let mut map = LruMap::new("test", Some(2));
+        map.insert(1, ());
+        map.insert(2, ());
+        map.insert(1, ());
+        map.insert(3, ());
+        assert_eq!(keys(&map), [1, 3]);
+        assert_eq!(map.order.len(), 2);
+    }
+
+    #[test]
+    fn removals_leave_the_index_in_sync() {
+        let mut map = LruMap::new("test", Some(3));
+        for key in 1..=3 {
+            map.insert(key, ());
+        }
+        map.remove(&1);
+        map.retain(|key, _| *key != 2);
+        assert_eq!(map.order.len(), 1);
+        map.clear();
+        assert!(map.order.is_empty());
+    }
+}
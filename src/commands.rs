@@ -0,0 +1,305 @@
+//! Slash commands, and the interactions they create.
+
+use std::time::Duration;
+
+use serde_json::json;
+use twilight_model::{
+    application::interaction::{Interaction, InteractionData},
+    channel::message::MessageFlags,
+    guild::Permissions,
+    http::interaction::{InteractionResponse, InteractionResponseData, InteractionResponseType},
+    id::{marker::ApplicationMarker, Id},
+};
+
+use crate::{config::MAX_MESSAGE_LENGTH, AppState, Error};
+
+const PING: &str = "xlr-ping";
+const CONFIG_SHOW: &str = "xlr-config-show";
+const MAINTENANCE: &str = "xlr-maintenance";
+const CACHE_DUMP: &str = "xlr-cache-dump";
+
+/// Every command as (name, description, default member permissions).
+const COMMANDS: &[(&str, &str, Option<Permissions>)] = &[
+    (PING, "Check that XLR is online and responsive", None),
+    (
+        CONFIG_SHOW,
+        "Show XLR's active configuration",
+        Some(Permissions::ADMINISTRATOR),
+    ),
+    (
+        MAINTENANCE,
+        "Toggle maintenance mode, where XLR ignores new threads",
+        Some(Permissions::ADMINISTRATOR),
+    ),
+    (
+        CACHE_DUMP,
+        "Show what XLR has cached about each channel",
+        Some(Permissions::ADMINISTRATOR),
+    ),
+];
+
+/// Create or update every command XLR handles.
+///
+/// A command that fails to register is logged and skipped, so one bad
+/// request doesn't leave the rest unregistered.
+pub async fn register(state: &AppState, application_id: Id<ApplicationMarker>) {
+    let mut registered = 0;
+    for (name, description, permissions) in COMMANDS {
+        let result = crate::retry::retry(state, || {
+            let request =
+                state
+                    .client
+                    .create_global_command(application_id, name, description, *permissions);
+            state.timed("create_global_command", request)
+        })
+        .await;
+        match result {
+            Ok(()) => registered += 1,
+            Err(source) => warn!(?source, name, "failed to register command"),
+        }
+    }
+    info!(registered, total = COMMANDS.len(), "registered commands");
+}
+
+pub async fn handle(
+    state: &AppState,
+    interaction: &Interaction,
+    latency: Option<Duration>,
+) -> Result<(), Error> {
+    if !crate::is_guild_allowed(&state.config, interaction.guild_id) {
+        debug!(
+            guild = interaction.guild_id.map(Id::get),
+            "Ignoring interaction outside the guild allowlist"
+        );
+        return Ok(());
+    }
+    let Some(InteractionData::ApplicationCommand(data)) = &interaction.data else {
+        return Ok(());
+    };
+    let content = match data.name.as_str() {
+        PING => ping(latency),
+        CONFIG_SHOW if is_admin(interaction) => config_show(state),
+        MAINTENANCE if is_admin(interaction) && is_operator(state, interaction) => {
+            if state.toggle_maintenance() {
+                "Maintenance mode is on, new threads will be ignored.".to_owned()
+            } else {
+                "Maintenance mode is off.".to_owned()
+            }
+        }
+        CACHE_DUMP if is_admin(interaction) => cache_dump(state),
+        MAINTENANCE if is_admin(interaction) => {
+            "Maintenance mode can only be toggled with SIGUSR2, unless you're listed in \
+             OPERATOR_IDS."
+                .to_owned()
+        }
+        CONFIG_SHOW | MAINTENANCE | CACHE_DUMP => {
+            "You need the Administrator permission to use this command.".to_owned()
+        }
+        name => {
+            debug!(name, "Ignoring unknown command");
+            return Ok(());
+        }
+    };
+    respond(state, interaction, content).await
+}
+
+fn ping(latency: Option<Duration>) -> String {
+    latency.map_or_else(
+        || "Pong! Gateway latency isn't known yet.".to_owned(),
+        |latency| format!("Pong! Gateway latency is {}ms.", latency.as_millis()),
+    )
+}
+
+/// The configuration summary, or a pointer to the log if it's too long to send.
+fn config_show(state: &AppState) -> String {
+    let summary = state.config.summary();
+    if summary.len() <= MAX_MESSAGE_LENGTH {
+        return summary;
+    }
+    info!(config = %summary, "active configuration");
+    "The configuration is too long to show here, it was written to the log instead.".to_owned()
+}
+
+/// Log the forum cache as JSON, and include it in the reply if it fits.
+fn cache_dump(state: &AppState) -> String {
+    let mut entries: Vec<_> = state
+        .forums
+        .read()
+        .iter()
+        .map(|(id, parent, age)| {
+            let entry = json!({
+                "channel_id": id.to_string(),
+                "guild_id": parent.guild_id.map(|id| id.to_string()),
+                "reactable": parent.reactable,
+                "read_only": parent.read_only,
+                "default_reaction": parent.default_reaction.as_ref().map(ToString::to_string),
+                "age_secs": age.as_secs(),
+            });
+            (*id, entry)
+        })
+        .collect();
+    entries.sort_unstable_by_key(|(id, _)| *id);
+    let dump =
+        serde_json::Value::Array(entries.into_iter().map(|(_, entry)| entry).collect()).to_string();
+    info!(cache = %dump, "forum cache dump");
+    let reply = format!("```json\n{dump}\n```");
+    if reply.len() <= MAX_MESSAGE_LENGTH {
+        reply
+    } else {
+        "The forum cache is too large to show here, it was written to the log instead.".to_owned()
+    }
+}
+
+/// Commands are registered as admin-only, but the server can override
+/// that, so check the invoking member's permissions again.
+fn is_admin(interaction: &Interaction) -> bool {
+    interaction
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.contains(Permissions::ADMINISTRATOR))
+}
+
+/// Maintenance mode affects every guild, so it's limited to the bot's operators.
+fn is_operator(state: &AppState, interaction: &Interaction) -> bool {
+    interaction
+        .author_id()
+        .is_some_and(|id| state.config.operator_ids.contains(&id))
+}
+
+/// Reply to an interaction with a message only the invoking user can see.
+async fn respond(
+    state: &AppState,
+    interaction: &Interaction,
+    content: String,
+) -> Result<(), Error> {
+    let response = InteractionResponse {
+        kind: InteractionResponseType::ChannelMessageWithSource,
+        data: Some(InteractionResponseData {
+            content: Some(content),
+            flags: Some(MessageFlags::EPHEMERAL),
+            ..Default::default()
+        }),
+    };
+    let request = state.client.create_interaction_response(
+        interaction.application_id,
+        interaction.id,
+        &interaction.token,
+        &response,
+    );
+    state.timed("create_interaction_response", request).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::mock::{self, Call, MockApi, AUTHOR, GUILD};
+
+    fn command(name: &str, guild_id: u64, permissions: &str) -> Interaction {
+        let interaction = json!({
+            "id": "20",
+            "application_id": "21",
+            "type": 2,
+            "token": "token",
+            "version": 1,
+            "guild_id": guild_id.to_string(),
+            "channel_id": "22",
+            "locale": "en-US",
+            "member": {
+                "user": {
+                    "id": AUTHOR.to_string(),
+                    "username": "author",
+                    "discriminator": "0",
+                    "avatar": null,
+                },
+                "roles": [],
+                "joined_at": "2023-01-01T00:00:00.000000+00:00",
+                "deaf": false,
+                "mute": false,
+                "flags": 0,
+                "permissions": permissions,
+            },
+            "data": {"id": "23", "name": name, "type": 1},
+        });
+        serde_json::from_value(interaction).expect("interaction fixture should deserialize")
+    }
+
+    fn replies(mock: &MockApi) -> Vec<String> {
+        mock.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::CreateInteractionResponse(_, content) => content,
+                _ => None,
+            })
+            .collect()
+    }
+
+    async fn maintenance(vars: &[(&str, &str)], permissions: &str) -> (AppState, Vec<String>) {
+        let mock = MockApi::default();
+        let state = mock::state(&mock, vars);
+        handle(&state, &command(MAINTENANCE, GUILD, permissions), None)
+            .await
+            .unwrap();
+        (state, replies(&mock))
+    }
+
+    #[tokio::test]
+    async fn other_guilds_are_ignored() {
+        let mock = MockApi::default();
+        let state = mock::state(&mock, &[("GUILD_ALLOWLIST", "5")]);
+        handle(&state, &command(PING, GUILD, "0"), None)
+            .await
+            .unwrap();
+        assert!(mock.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn allowlisted_guilds_get_replies() {
+        let mock = MockApi::default();
+        let state = mock::state(&mock, &[("GUILD_ALLOWLIST", &GUILD.to_string())]);
+        handle(&state, &command(PING, GUILD, "0"), None)
+            .await
+            .unwrap();
+        assert_eq!(replies(&mock).len(), 1);
+    }
+
+    #[test]
+    fn long_configs_go_to_the_log() {
+        let mock = MockApi::default();
+        let emoji: Vec<_> = (1..200).map(|id| format!("emoji:{id}")).collect();
+        let state = mock::state(&mock, &[("REACTION_EMOJI", &emoji.join(","))]);
+        assert!(state.config.summary().len() > MAX_MESSAGE_LENGTH);
+        assert!(config_show(&state).contains("written to the log"));
+    }
+
+    #[test]
+    fn short_configs_are_shown() {
+        let mock = MockApi::default();
+        let state = mock::state(&mock, &[]);
+        assert_eq!(config_show(&state), state.config.summary());
+    }
+
+    #[tokio::test]
+    async fn maintenance_needs_an_operator() {
+        let (state, replies) = maintenance(&[], "8").await;
+        assert!(!state.maintenance.load(Ordering::Relaxed));
+        assert!(replies[0].contains("SIGUSR2"));
+    }
+
+    #[tokio::test]
+    async fn maintenance_needs_an_admin() {
+        let (state, replies) = maintenance(&[("OPERATOR_IDS", &AUTHOR.to_string())], "0").await;
+        assert!(!state.maintenance.load(Ordering::Relaxed));
+        assert!(replies[0].contains("Administrator"));
+    }
+
+    #[tokio::test]
+    async fn operators_toggle_maintenance() {
+        let (state, replies) = maintenance(&[("OPERATOR_IDS", &AUTHOR.to_string())], "8").await;
+        assert!(state.maintenance.load(Ordering::Relaxed));
+        assert!(replies[0].contains("is on"));
+    }
+}
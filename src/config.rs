@@ -0,0 +1,997 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use ahash::AHashMap;
+use hyper::Uri;
+use twilight_model::{
+    channel::thread::AutoArchiveDuration,
+    gateway::Intents,
+    id::{
+        marker::{ChannelMarker, GuildMarker, RoleMarker, UserMarker},
+        Id,
+    },
+};
+
+use crate::{
+    forums::UnknownChannelAction,
+    logging::LogRotation,
+    reactions::{Emoji, KeywordRule, ReactionTarget, DISCORD_MAX_REACTIONS},
+};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub token: Token,
+    /// Send API requests here instead of to Discord, e.g. a mock server or proxy
+    pub api_url: Option<ApiUrl>,
+    pub metrics_addr: Option<SocketAddr>,
+    /// Gateway intents to request on top of the ones XLR's features need
+    pub gateway_intents: Intents,
+    /// Where to post a JSON event for every handled thread
+    pub event_webhook_url: Option<SecretUrl>,
+    /// External session-start queue to identify through, instead of the local one
+    pub gateway_queue_url: Option<SecretUrl>,
+    /// Where to save the gateway session on shutdown so the next run can resume it
+    pub session_file: Option<PathBuf>,
+    /// How often to log cache sizes, if at all
+    pub cache_stats_interval: Option<Duration>,
+    /// How often to log a summary of Discord API requests, if at all
+    pub http_stats_interval: Option<Duration>,
+    /// Warn when threads are handled this long after they were created
+    pub backlog_warn: Option<Duration>,
+    /// Write logs to stdout
+    pub log_stdout: bool,
+    /// Directory to write rotating log files to, if any
+    pub log_dir: Option<PathBuf>,
+    pub log_rotation: LogRotation,
+    /// Rotated log files to keep, unlimited if unset
+    pub log_max_files: Option<usize>,
+    /// Only act in these guilds, or in every guild if empty
+    pub guild_allowlist: Vec<Id<GuildMarker>>,
+    /// Users allowed to toggle maintenance mode with `/xlr-maintenance`
+    pub operator_ids: Vec<Id<UserMarker>>,
+    /// Threads with fewer characters in their title are skipped
+    pub min_title_length: usize,
+    /// Emoji added to every forum post, in order
+    pub reaction_emoji: Vec<Emoji>,
+    /// Per-guild replacements for `reaction_emoji`, read from `GUILD_EMOJI_FILE`
+    pub guild_emoji: AHashMap<Id<GuildMarker>, Vec<Emoji>>,
+    /// Emoji added on top of the others when a thread's title contains a keyword
+    pub keyword_emoji: Vec<KeywordRule>,
+    /// Check keyword rules again when a thread is edited, adding emoji for new matches
+    pub keywords_on_edit: bool,
+    /// React with a forum's own default reaction emoji, if it has one, instead of `reaction_emoji`
+    pub use_forum_default_reaction: bool,
+    /// Emoji used instead of `reaction_emoji` on threads started by bots
+    pub bot_thread_emoji: Vec<Emoji>,
+    /// Emoji used on threads that are locked, or in a read-only forum with
+    /// `lock_read_only_forums`. Those threads are skipped if this is empty.
+    pub locked_emoji: Vec<Emoji>,
+    /// Treat forums where `@everyone` can't post as locked. Off by default,
+    /// since staff-only forums that members vote in are common.
+    pub lock_read_only_forums: bool,
+    /// Emoji used instead of `reaction_emoji` on threads whose author has one of these roles.
+    /// Checking costs a member fetch per thread, so this is off if empty.
+    pub role_emoji: AHashMap<Id<RoleMarker>, Emoji>,
+    /// How long fetched member roles are reused for
+    pub member_cache_ttl: Duration,
+    /// Most thread authors to remember roles for, unlimited if unset
+    pub member_cache_max: Option<usize>,
+    /// Emoji used instead of `reaction_emoji` when the message is a crosspost
+    /// from a followed announcement channel
+    pub crosspost_emoji: Vec<Emoji>,
+    /// Don't react when the message is a crosspost
+    pub skip_crossposts: bool,
+    /// Don't react when the message was posted by a webhook, like an integration
+    pub skip_webhook_threads: bool,
+    /// Users whose threads count as bot threads, in addition to this bot
+    pub bot_authors: Vec<Id<UserMarker>>,
+    pub reaction_target: ReactionTarget,
+    /// How long to wait for edits before reading the thread again and reacting
+    pub reaction_grace: Option<Duration>,
+    /// Message to post in new threads, see the README for placeholders
+    pub welcome_message: Option<String>,
+    /// Show the bot typing for this long before posting the welcome message
+    pub welcome_typing: Option<Duration>,
+    /// Remove the bot's own reactions this long after adding them, so only members' votes remain
+    pub remove_reactions_after: Option<Duration>,
+    /// Where to keep reactions waiting to be removed, so removal survives restarts
+    pub reaction_removal_file: Option<PathBuf>,
+    /// Auto-archive duration in minutes to give new threads
+    pub auto_archive_minutes: Option<u16>,
+    /// Per-forum auto-archive durations, taking precedence over `auto_archive_minutes`
+    pub forum_auto_archive_minutes: AHashMap<Id<ChannelMarker>, u16>,
+    /// Fetch the starter message before reacting to make sure it exists
+    pub require_starter_message: bool,
+    /// Fetch the message before reacting and leave out emoji someone already reacted with
+    pub skip_existing_reactions: bool,
+    /// Pause between each reaction on a thread
+    pub reaction_interval: Option<Duration>,
+    /// Upper bound on reactions added to a single post across all rule sources
+    pub max_reactions: usize,
+    /// Also react to threads created in announcement channels
+    pub react_in_announcements: bool,
+    pub on_unknown_channel_type: UnknownChannelAction,
+    /// How long to skip threads in a forum the bot was denied access to before checking again
+    pub no_access_cooldown: Duration,
+    /// Most channels to remember the classification of, unlimited if unset
+    pub forum_cache_max: Option<usize>,
+    /// Fetch the channels of every guild on startup instead of classifying them on demand
+    pub preseed_cache: bool,
+    /// Whether a SIGUSR1 rescan refetches every guild's channels after clearing the cache
+    pub rescan_reseed: bool,
+    /// Threads in one forum within `burst_window` that count as a burst, off if unset
+    pub burst_threshold: Option<u32>,
+    pub burst_window: Duration,
+    /// How long to stop reacting in a forum after a burst. Bursts are only logged if unset.
+    pub burst_cooldown: Option<Duration>,
+    /// Retries the whole process may make in a burst, 0 to never retry
+    pub retry_budget: u32,
+    /// How long it takes to earn back one retry
+    pub retry_refill: Duration,
+    /// Consecutive handler errors that put the bot into safe mode
+    pub safe_mode_threshold: u32,
+    /// Errors further apart than this are not considered consecutive
+    pub safe_mode_window: Duration,
+    /// How long safe mode lasts before the bot tries again
+    pub safe_mode_cooldown: Duration,
+}
+
+impl Config {
+    /// A human-readable overview of the active configuration, with secrets left out.
+    pub fn summary(&self) -> String {
+        fn emoji_list(emoji: &[Emoji]) -> String {
+            if emoji.is_empty() {
+                return "none".to_owned();
+            }
+            emoji
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+        fn on_off(enabled: bool) -> &'static str {
+            if enabled {
+                "on"
+            } else {
+                "off"
+            }
+        }
+        fn limit(max: Option<usize>) -> String {
+            max.map_or_else(|| "unlimited".to_owned(), |max| max.to_string())
+        }
+        fn secs(duration: Option<Duration>) -> String {
+            duration.map_or_else(|| "off".to_owned(), |d| format!("{}s", d.as_secs_f64()))
+        }
+        let allowlist = if self.guild_allowlist.is_empty() {
+            "all guilds".to_owned()
+        } else {
+            format!("{} guilds", self.guild_allowlist.len())
+        };
+        let auto_archive = self
+            .auto_archive_minutes
+            .map_or_else(|| "off".to_owned(), |minutes| format!("{minutes} minutes"));
+        [
+            format!("Reaction emoji: {}", emoji_list(&self.reaction_emoji)),
+            format!(
+                "Keyword rules: {}, rechecked on edit: {}",
+                self.keyword_emoji.len(),
+                on_off(self.keywords_on_edit)
+            ),
+            format!(
+                "Forum default reactions: {}",
+                on_off(self.use_forum_default_reaction)
+            ),
+            format!("Guild emoji overrides: {}", self.guild_emoji.len()),
+            format!("Bot thread emoji: {}", emoji_list(&self.bot_thread_emoji)),
+            format!("Locked forum emoji: {}", emoji_list(&self.locked_emoji)),
+            format!(
+                "Read-only forums locked: {}",
+                on_off(self.lock_read_only_forums)
+            ),
+            format!("Role emoji: {} roles", self.role_emoji.len()),
+            format!(
+                "Crossposts: {}",
+                if self.skip_crossposts {
+                    "skipped".to_owned()
+                } else {
+                    emoji_list(&self.crosspost_emoji)
+                }
+            ),
+            format!(
+                "Webhook threads: {}",
+                if self.skip_webhook_threads {
+                    "skipped"
+                } else {
+                    "reacted to"
+                }
+            ),
+            format!("Bot authors: {}", self.bot_authors.len()),
+            format!("Operators: {}", self.operator_ids.len()),
+            format!("Reaction target: {:?}", self.reaction_target),
+            format!("Max reactions: {}", self.max_reactions),
+            format!("Min title length: {}", self.min_title_length),
+            format!("Guild allowlist: {allowlist}"),
+            format!(
+                "Announcement channels: {}",
+                on_off(self.react_in_announcements)
+            ),
+            format!("Unknown channel types: {:?}", self.on_unknown_channel_type),
+            format!("Grace period: {}", secs(self.reaction_grace)),
+            format!("Reaction interval: {}", secs(self.reaction_interval)),
+            format!(
+                "Require starter message: {}",
+                on_off(self.require_starter_message)
+            ),
+            format!(
+                "Skip existing reactions: {}",
+                on_off(self.skip_existing_reactions)
+            ),
+            format!(
+                "Welcome message: {}, typing first: {}",
+                on_off(self.welcome_message.is_some()),
+                secs(self.welcome_typing)
+            ),
+            format!(
+                "Remove reactions after: {}{}",
+                secs(self.remove_reactions_after),
+                if self.reaction_removal_file.is_some() {
+                    ", persisted"
+                } else {
+                    ""
+                }
+            ),
+            format!(
+                "Auto-archive: {auto_archive}, {} forum overrides",
+                self.forum_auto_archive_minutes.len()
+            ),
+            self.burst_threshold.map_or_else(
+                || "Burst detection: off".to_owned(),
+                |threshold| {
+                    format!(
+                        "Burst detection: {threshold} threads within {}s, cooldown {}",
+                        self.burst_window.as_secs(),
+                        secs(self.burst_cooldown)
+                    )
+                },
+            ),
+            format!(
+                "Retry budget: {}, one more every {}s",
+                self.retry_budget,
+                self.retry_refill.as_secs()
+            ),
+            format!(
+                "Safe mode: {} errors within {}s, {}s cooldown",
+                self.safe_mode_threshold,
+                self.safe_mode_window.as_secs(),
+                self.safe_mode_cooldown.as_secs()
+            ),
+            format!("Gateway intents: {:?}", self.intents()),
+            format!("Custom API URL: {}", on_off(self.api_url.is_some())),
+            format!("Metrics endpoint: {}", on_off(self.metrics_addr.is_some())),
+            format!(
+                "Event webhook: {}",
+                on_off(self.event_webhook_url.is_some())
+            ),
+            format!(
+                "Gateway queue: {}",
+                if self.gateway_queue_url.is_some() {
+                    "external"
+                } else {
+                    "local"
+                }
+            ),
+            format!("Session resume: {}", on_off(self.session_file.is_some())),
+            format!("Pre-seed cache: {}", on_off(self.preseed_cache)),
+            format!(
+                "Cache limits: {} channels, {} members",
+                limit(self.forum_cache_max),
+                limit(self.member_cache_max)
+            ),
+            format!(
+                "Member roles cached for: {}s",
+                self.member_cache_ttl.as_secs()
+            ),
+            format!(
+                "Unviewable channels retried after: {}s",
+                self.no_access_cooldown.as_secs()
+            ),
+            format!("Rescan re-seeds cache: {}", on_off(self.rescan_reseed)),
+            format!(
+                "Stats logged every: cache {}, requests {}",
+                secs(self.cache_stats_interval),
+                secs(self.http_stats_interval)
+            ),
+            format!("Backlog warning after: {}", secs(self.backlog_warn)),
+            format!(
+                "Logging: stdout {}, files {}",
+                on_off(self.log_stdout),
+                self.log_dir.as_ref().map_or_else(
+                    || "off".to_owned(),
+                    |_| format!(
+                        "rotated {:?}, keeping {}",
+                        self.log_rotation,
+                        limit(self.log_max_files)
+                    )
+                )
+            ),
+        ]
+        .join("\n")
+    }
+
+    /// The default emoji for threads in a guild and forum.
+    ///
+    /// The guild's configured emoji come first, since they were set up on
+    /// purpose, then the forum's own default reaction, then `reaction_emoji`.
+    pub fn reaction_emoji_for<'a>(
+        &'a self,
+        guild_id: Option<Id<GuildMarker>>,
+        forum_default: Option<&'a Emoji>,
+    ) -> &'a [Emoji] {
+        guild_id
+            .and_then(|guild_id| self.guild_emoji.get(&guild_id))
+            .map(Vec::as_slice)
+            .or_else(|| forum_default.map(std::slice::from_ref))
+            .unwrap_or(&self.reaction_emoji)
+    }
+
+    /// Every intent to identify with: the configured ones, plus what enabled features need.
+    pub fn intents(&self) -> Intents {
+        // Thread and channel events, and the guild list, all come with GUILDS.
+        // Everything else XLR needs is fetched over HTTP, which takes no intents.
+        self.gateway_intents | Intents::GUILDS
+    }
+
+    /// Whether handlers may sleep between steps, or back off before
+    /// retrying, in which case they run in their own task so they don't hold
+    /// up the event loop.
+    pub const fn waits_between_steps(&self) -> bool {
+        self.reaction_grace.is_some()
+            || self.reaction_interval.is_some()
+            || self.welcome_typing.is_some()
+            || self.retry_budget > 0
+    }
+
+    /// Whether crossposted messages are treated differently, which means
+    /// fetching the starter message to read its flags.
+    pub fn checks_crossposts(&self) -> bool {
+        self.skip_crossposts || !self.crosspost_emoji.is_empty()
+    }
+
+    /// Read the configuration from the environment, returning every problem
+    /// with it at once rather than stopping at the first.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Like [`Config::from_env`], but looking variables up with `lookup`.
+    pub fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut vars = Vars {
+            lookup,
+            problems: Vec::new(),
+        };
+        let token = vars.raw("DISCORD_TOKEN").unwrap_or_else(|| {
+            vars.problem("DISCORD_TOKEN is required".to_owned());
+            String::new()
+        });
+        let config = Self {
+            token: Token(token),
+            api_url: vars.var("DISCORD_API_URL"),
+            metrics_addr: vars.var("METRICS_ADDR"),
+            gateway_intents: vars
+                .list("GATEWAY_INTENTS")
+                .unwrap_or_default()
+                .into_iter()
+                .fold(Intents::empty(), |intents, GatewayIntent(intent)| {
+                    intents | intent
+                }),
+            event_webhook_url: vars.var("EVENT_WEBHOOK_URL"),
+            gateway_queue_url: vars.var("GATEWAY_QUEUE_URL"),
+            session_file: vars.var("SESSION_FILE"),
+            cache_stats_interval: vars
+                .var("CACHE_STATS_INTERVAL_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            http_stats_interval: vars
+                .var("HTTP_STATS_INTERVAL_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            backlog_warn: Some(vars.var("BACKLOG_WARN_SECS").unwrap_or(30))
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            log_stdout: vars.var("LOG_STDOUT").unwrap_or(true),
+            log_dir: vars.var("LOG_DIR"),
+            log_rotation: vars.var("LOG_ROTATION").unwrap_or(LogRotation::Daily),
+            log_max_files: vars.var("LOG_MAX_FILES"),
+            guild_allowlist: vars.list("GUILD_ALLOWLIST").unwrap_or_default(),
+            operator_ids: vars.list("OPERATOR_IDS").unwrap_or_default(),
+            min_title_length: vars.var("MIN_TITLE_LENGTH").unwrap_or(0),
+            reaction_emoji: vars
+                .list("REACTION_EMOJI")
+                .unwrap_or_else(|| vec![Emoji::Unicode("⬆️".to_owned())]),
+            guild_emoji: vars
+                .raw("GUILD_EMOJI_FILE")
+                .map(|path| vars.guild_emoji(Path::new(&path)))
+                .unwrap_or_default(),
+            keyword_emoji: vars.list("KEYWORD_EMOJI").unwrap_or_default(),
+            keywords_on_edit: vars.var("KEYWORDS_ON_EDIT").unwrap_or(false),
+            use_forum_default_reaction: vars.var("USE_FORUM_DEFAULT_REACTION").unwrap_or(true),
+            bot_thread_emoji: vars.list("BOT_THREAD_EMOJI").unwrap_or_default(),
+            locked_emoji: vars.list("LOCKED_FORUM_EMOJI").unwrap_or_default(),
+            lock_read_only_forums: vars.var("LOCK_READ_ONLY_FORUMS").unwrap_or(false),
+            role_emoji: vars.map("ROLE_EMOJI").unwrap_or_default(),
+            member_cache_ttl: Duration::from_secs(vars.var("MEMBER_CACHE_SECS").unwrap_or(60)),
+            member_cache_max: vars.var("MEMBER_CACHE_MAX").filter(|max| *max > 0),
+            crosspost_emoji: vars.list("CROSSPOST_EMOJI").unwrap_or_default(),
+            skip_crossposts: vars.var("SKIP_CROSSPOSTS").unwrap_or(false),
+            skip_webhook_threads: vars.var("SKIP_WEBHOOK_THREADS").unwrap_or(false),
+            bot_authors: vars.list("BOT_AUTHORS").unwrap_or_default(),
+            reaction_target: vars.var("REACTION_TARGET").unwrap_or_default(),
+            reaction_grace: vars
+                .var("REACTION_GRACE_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            welcome_message: vars
+                .raw("WELCOME_MESSAGE")
+                .filter(|message| !message.trim().is_empty()),
+            welcome_typing: vars
+                .var("WELCOME_TYPING_MS")
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
+            remove_reactions_after: vars
+                .var("REMOVE_REACTIONS_AFTER_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            reaction_removal_file: vars.var("REACTION_REMOVAL_FILE"),
+            auto_archive_minutes: vars.var("AUTO_ARCHIVE_MINUTES"),
+            forum_auto_archive_minutes: vars.map("FORUM_AUTO_ARCHIVE_MINUTES").unwrap_or_default(),
+            require_starter_message: vars.var("REQUIRE_STARTER_MESSAGE").unwrap_or(false),
+            skip_existing_reactions: vars.var("SKIP_EXISTING_REACTIONS").unwrap_or(false),
+            reaction_interval: vars
+                .var("REACTION_INTERVAL_MS")
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
+            max_reactions: vars
+                .var("MAX_REACTIONS")
+                .unwrap_or(DISCORD_MAX_REACTIONS)
+                .min(DISCORD_MAX_REACTIONS),
+            react_in_announcements: vars.var("REACT_IN_ANNOUNCEMENTS").unwrap_or(false),
+            on_unknown_channel_type: vars.var("ON_UNKNOWN_CHANNEL_TYPE").unwrap_or_default(),
+            no_access_cooldown: Duration::from_secs(
+                vars.var("NO_ACCESS_COOLDOWN_SECS").unwrap_or(600),
+            ),
+            forum_cache_max: vars.var("FORUM_CACHE_MAX").filter(|max| *max > 0),
+            preseed_cache: vars.var("PRESEED_CACHE").unwrap_or(false),
+            rescan_reseed: vars.var("RESCAN_RESEED").unwrap_or(false),
+            burst_threshold: vars.var("BURST_THRESHOLD").filter(|threads| *threads > 0),
+            burst_window: Duration::from_secs(vars.var("BURST_WINDOW_SECS").unwrap_or(60)),
+            burst_cooldown: vars
+                .var("BURST_COOLDOWN_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            retry_budget: vars.var("RETRY_BUDGET").unwrap_or(10),
+            retry_refill: Duration::from_secs(vars.var("RETRY_REFILL_SECS").unwrap_or(6)),
+            safe_mode_threshold: vars.var("SAFE_MODE_THRESHOLD").unwrap_or(10),
+            safe_mode_window: Duration::from_secs(vars.var("SAFE_MODE_WINDOW_SECS").unwrap_or(60)),
+            safe_mode_cooldown: Duration::from_secs(
+                vars.var("SAFE_MODE_COOLDOWN_SECS").unwrap_or(300),
+            ),
+        };
+        let mut problems = vars.problems;
+        if let Err(ConfigError(invalid)) = config.validate() {
+            problems.extend(invalid);
+        }
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+
+    /// Check values that parsed but can't work, like zero-length durations
+    /// or archive durations Discord doesn't offer.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        if self
+            .gateway_queue_url
+            .as_ref()
+            .is_some_and(|url| url.as_uri().scheme_str() != Some("http"))
+        {
+            problems.push("GATEWAY_QUEUE_URL must be an http:// URL".to_owned());
+        }
+        if self
+            .event_webhook_url
+            .as_ref()
+            .is_some_and(|url| !matches!(url.as_uri().scheme_str(), Some("http" | "https")))
+        {
+            problems.push("EVENT_WEBHOOK_URL must be an http:// or https:// URL".to_owned());
+        }
+        for (name, duration) in [
+            ("MEMBER_CACHE_SECS", self.member_cache_ttl),
+            ("NO_ACCESS_COOLDOWN_SECS", self.no_access_cooldown),
+            ("BURST_WINDOW_SECS", self.burst_window),
+            ("SAFE_MODE_WINDOW_SECS", self.safe_mode_window),
+        ] {
+            if duration.is_zero() {
+                problems.push(format!("{name} must be greater than 0"));
+            }
+        }
+        if self.safe_mode_threshold == 0 {
+            problems.push("SAFE_MODE_THRESHOLD must be greater than 0".to_owned());
+        }
+        if self.retry_budget > 0 && self.retry_refill.is_zero() {
+            problems.push("RETRY_REFILL_SECS must be greater than 0".to_owned());
+        }
+        if self.max_reactions == 0 {
+            problems.push("MAX_REACTIONS must be greater than 0".to_owned());
+        }
+        if let Some(minutes) = self.auto_archive_minutes {
+            if !is_archive_duration(minutes) {
+                problems.push(format!(
+                    "AUTO_ARCHIVE_MINUTES `{minutes}` must be 60, 1440, 4320 or 10080"
+                ));
+            }
+        }
+        for (forum, minutes) in &self.forum_auto_archive_minutes {
+            if !is_archive_duration(*minutes) {
+                problems.push(format!(
+                    "FORUM_AUTO_ARCHIVE_MINUTES entry `{forum}:{minutes}` must be 60, 1440, 4320 or 10080"
+                ));
+            }
+        }
+        if self
+            .welcome_message
+            .as_ref()
+            .is_some_and(|message| message.chars().count() > MAX_MESSAGE_LENGTH)
+        {
+            problems.push(format!(
+                "WELCOME_MESSAGE must be at most {MAX_MESSAGE_LENGTH} characters"
+            ));
+        }
+        // The directory is created when logging is set up, but a file in the
+        // way can be caught now
+        if let Some(dir) = &self.log_dir {
+            if dir.exists() && !dir.is_dir() {
+                problems.push(format!("LOG_DIR `{}` is not a directory", dir.display()));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+}
+
+/// Discord's limit on message length, in characters.
+pub const MAX_MESSAGE_LENGTH: usize = 2000;
+
+fn is_archive_duration(minutes: u16) -> bool {
+    !matches!(
+        AutoArchiveDuration::from(minutes),
+        AutoArchiveDuration::Unknown { .. }
+    )
+}
+
+/// Everything wrong with the configuration, one problem per line.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} configuration problem(s):", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Bot token, redacted from `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Token(String);
+
+impl Token {
+    pub const fn new(token: String) -> Self {
+        Self(token)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Token([redacted])")
+    }
+}
+
+/// A URL that may carry a secret in its path or query, like a webhook's,
+/// redacted from `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretUrl(Uri);
+
+impl SecretUrl {
+    pub const fn as_uri(&self) -> &Uri {
+        &self.0
+    }
+
+    /// The host, which is safe to log.
+    pub fn host(&self) -> &str {
+        self.0.host().unwrap_or_default()
+    }
+}
+
+impl FromStr for SecretUrl {
+    type Err = hyper::http::uri::InvalidUri;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+impl fmt::Debug for SecretUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretUrl([redacted])")
+    }
+}
+
+/// Base URL for the Discord API, as twilight's proxy setting expects it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiUrl {
+    /// Host and optional path, without the scheme
+    pub host: String,
+    /// Whether to use plain HTTP rather than HTTPS
+    pub use_http: bool,
+}
+
+impl FromStr for ApiUrl {
+    type Err = ApiUrlParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, use_http) = if let Some(host) = s.strip_prefix("http://") {
+            (host, true)
+        } else if let Some(host) = s.strip_prefix("https://") {
+            (host, false)
+        } else {
+            return Err(ApiUrlParseError(s.to_owned()));
+        };
+        let host = host.trim_end_matches('/');
+        if host.is_empty() {
+            return Err(ApiUrlParseError(s.to_owned()));
+        }
+        Ok(Self {
+            host: host.to_owned(),
+            use_http,
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid API URL `{0}`, expected http://host or https://host")]
+pub struct ApiUrlParseError(String);
+
+/// A single gateway intent, named like the [`Intents`] constant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GatewayIntent(pub Intents);
+
+impl FromStr for GatewayIntent {
+    type Err = GatewayIntentParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let intent = match s.to_ascii_uppercase().as_str() {
+            "GUILDS" => Intents::GUILDS,
+            "GUILD_MEMBERS" => Intents::GUILD_MEMBERS,
+            "GUILD_MODERATION" | "GUILD_BANS" => Intents::GUILD_MODERATION,
+            "GUILD_EMOJIS_AND_STICKERS" => Intents::GUILD_EMOJIS_AND_STICKERS,
+            "GUILD_INTEGRATIONS" => Intents::GUILD_INTEGRATIONS,
+            "GUILD_WEBHOOKS" => Intents::GUILD_WEBHOOKS,
+            "GUILD_INVITES" => Intents::GUILD_INVITES,
+            "GUILD_VOICE_STATES" => Intents::GUILD_VOICE_STATES,
+            "GUILD_PRESENCES" => Intents::GUILD_PRESENCES,
+            "GUILD_MESSAGES" => Intents::GUILD_MESSAGES,
+            "GUILD_MESSAGE_REACTIONS" => Intents::GUILD_MESSAGE_REACTIONS,
+            "GUILD_MESSAGE_TYPING" => Intents::GUILD_MESSAGE_TYPING,
+            "DIRECT_MESSAGES" => Intents::DIRECT_MESSAGES,
+            "DIRECT_MESSAGE_REACTIONS" => Intents::DIRECT_MESSAGE_REACTIONS,
+            "DIRECT_MESSAGE_TYPING" => Intents::DIRECT_MESSAGE_TYPING,
+            "MESSAGE_CONTENT" => Intents::MESSAGE_CONTENT,
+            "GUILD_SCHEDULED_EVENTS" => Intents::GUILD_SCHEDULED_EVENTS,
+            "AUTO_MODERATION_CONFIGURATION" => Intents::AUTO_MODERATION_CONFIGURATION,
+            "AUTO_MODERATION_EXECUTION" => Intents::AUTO_MODERATION_EXECUTION,
+            _ => return Err(GatewayIntentParseError(s.to_owned())),
+        };
+        Ok(Self(intent))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown gateway intent `{0}`, expected a name like GUILD_MESSAGES")]
+pub struct GatewayIntentParseError(String);
+
+/// Reads environment variables, noting problems instead of stopping at the first.
+struct Vars<F> {
+    lookup: F,
+    problems: Vec<String>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Vars<F> {
+    fn raw(&self, name: &str) -> Option<String> {
+        (self.lookup)(name)
+    }
+
+    fn problem(&mut self, problem: String) {
+        self.problems.push(problem);
+    }
+
+    fn var<T>(&mut self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let value = self.raw(name)?;
+        match value.parse() {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.problem(format!("Failed to parse {name} environment variable: {e}"));
+                None
+            }
+        }
+    }
+
+    /// Parse a comma-separated list, ignoring empty entries.
+    fn list<T>(&mut self, name: &str) -> Option<Vec<T>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let value = self.raw(name)?;
+        let list = value
+            .split(',')
+            .filter(|v| !v.trim().is_empty())
+            .filter_map(|v| match v.trim().parse() {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    self.problem(format!("Failed to parse {name} entry `{v}`: {e}"));
+                    None
+                }
+            })
+            .collect();
+        Some(list)
+    }
+
+    /// Parse a comma-separated list of `key:value` pairs.
+    fn map<K, V>(&mut self, name: &str) -> Option<AHashMap<K, V>>
+    where
+        K: FromStr + Eq + std::hash::Hash,
+        K::Err: std::fmt::Display,
+        V: FromStr,
+        V::Err: std::fmt::Display,
+    {
+        let value = self.raw(name)?;
+        let map = value
+            .split(',')
+            .filter(|v| !v.trim().is_empty())
+            .filter_map(|entry| {
+                let Some((key, value)) = entry.split_once(':') else {
+                    self.problem(format!(
+                        "Failed to parse {name} entry `{entry}`: expected key:value"
+                    ));
+                    return None;
+                };
+                let key = key
+                    .trim()
+                    .parse()
+                    .map_err(|e| self.problem(format!("Failed to parse {name} key `{key}`: {e}")));
+                let value = value.trim().parse().map_err(|e| {
+                    self.problem(format!("Failed to parse {name} value `{value}`: {e}"));
+                });
+                Some((key.ok()?, value.ok()?))
+            })
+            .collect();
+        Some(map)
+    }
+
+    /// Read per-guild emoji from a JSON object of guild ids to emoji lists, like
+    /// `{"123": ["⬆️", "name:456"]}`.
+    fn guild_emoji(&mut self, path: &Path) -> AHashMap<Id<GuildMarker>, Vec<Emoji>> {
+        let path_display = path.display();
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                self.problem(format!(
+                    "Failed to read GUILD_EMOJI_FILE `{path_display}`: {e}"
+                ));
+                return AHashMap::new();
+            }
+        };
+        let guilds: std::collections::HashMap<String, Vec<String>> =
+            match serde_json::from_slice(&data) {
+                Ok(guilds) => guilds,
+                Err(e) => {
+                    self.problem(format!(
+                        "Failed to parse GUILD_EMOJI_FILE `{path_display}`: {e}"
+                    ));
+                    return AHashMap::new();
+                }
+            };
+        guilds
+            .into_iter()
+            .filter_map(|(guild_id, emoji)| {
+                let guild_id = match guild_id.parse() {
+                    Ok(guild_id) => guild_id,
+                    Err(e) => {
+                        self.problem(format!(
+                            "Failed to parse GUILD_EMOJI_FILE guild id `{guild_id}`: {e}"
+                        ));
+                        return None;
+                    }
+                };
+                let emoji = emoji
+                    .iter()
+                    .filter_map(|emoji| match emoji.parse() {
+                        Ok(emoji) => Some(emoji),
+                        Err(e) => {
+                            self.problem(format!(
+                                "Failed to parse GUILD_EMOJI_FILE emoji `{emoji}`: {e}"
+                            ));
+                            None
+                        }
+                    })
+                    .collect();
+                Some((guild_id, emoji))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    fn emoji(s: &str) -> Emoji {
+        s.parse().unwrap()
+    }
+
+    /// Every problem with the config made from `vars` on top of a token.
+    fn problems(vars: &[(&str, &str)]) -> Vec<String> {
+        let result = Config::from_vars(|name| {
+            if name == "DISCORD_TOKEN" {
+                return Some("test".to_owned());
+            }
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (*value).to_owned())
+        });
+        result
+            .err()
+            .map(|ConfigError(problems)| problems)
+            .unwrap_or_default()
+    }
+
+    /// Config with guild emoji for guilds 1 and 2, read from a file like in production.
+    /// `name` keeps the file apart from other tests running at the same time.
+    fn guild_emoji_config(name: &str) -> Config {
+        let path = std::env::temp_dir().join(format!("xlr-{name}-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"1": ["⬆️", "⬇️"], "2": ["⭐"]}"#).unwrap();
+        let config = mock::config(&[
+            ("GUILD_EMOJI_FILE", path.to_str().unwrap()),
+            ("REACTION_EMOJI", "👍"),
+        ]);
+        std::fs::remove_file(path).unwrap();
+        config
+    }
+
+    #[test]
+    fn guilds_resolve_their_own_emoji() {
+        let config = guild_emoji_config("guilds_resolve_their_own_emoji");
+        assert_eq!(
+            config.reaction_emoji_for(Some(Id::new(1)), None),
+            [emoji("⬆️"), emoji("⬇️")]
+        );
+        assert_eq!(
+            config.reaction_emoji_for(Some(Id::new(2)), None),
+            [emoji("⭐")]
+        );
+        assert_eq!(
+            config.reaction_emoji_for(Some(Id::new(3)), None),
+            [emoji("👍")]
+        );
+        assert_eq!(config.reaction_emoji_for(None, None), [emoji("👍")]);
+    }
+
+    #[test]
+    fn guild_emoji_beat_forum_default() {
+        let config = guild_emoji_config("guild_emoji_beat_forum_default");
+        let forum_default = emoji("🔥");
+        assert_eq!(
+            config.reaction_emoji_for(Some(Id::new(2)), Some(&forum_default)),
+            [emoji("⭐")]
+        );
+        assert_eq!(
+            config.reaction_emoji_for(Some(Id::new(3)), Some(&forum_default)),
+            [emoji("🔥")]
+        );
+    }
+
+    #[test]
+    fn summary_shows_cache_timings() {
+        let config = mock::config(&[
+            ("MEMBER_CACHE_SECS", "90"),
+            ("NO_ACCESS_COOLDOWN_SECS", "120"),
+        ]);
+        let summary = config.summary();
+        assert!(summary.contains("Member roles cached for: 90s"));
+        assert!(summary.contains("Unviewable channels retried after: 120s"));
+    }
+
+    #[test]
+    fn retries_need_a_refill() {
+        assert_eq!(
+            problems(&[("RETRY_REFILL_SECS", "0")]),
+            ["RETRY_REFILL_SECS must be greater than 0"]
+        );
+        assert!(problems(&[("RETRY_REFILL_SECS", "0"), ("RETRY_BUDGET", "0")]).is_empty());
+    }
+
+    #[test]
+    fn log_dir_must_be_a_directory() {
+        let path = std::env::temp_dir().join(format!("xlr-log-dir-{}", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let problems = problems(&[("LOG_DIR", path.to_str().unwrap())]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].ends_with("is not a directory"));
+    }
+
+    #[test]
+    fn missing_log_dir_is_left_alone() {
+        let path = std::env::temp_dir().join(format!("xlr-missing-log-dir-{}", std::process::id()));
+        assert!(problems(&[("LOG_DIR", path.to_str().unwrap())]).is_empty());
+        // Creating it is up to logging
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn welcome_message_must_fit_in_a_message() {
+        let message = "a".repeat(MAX_MESSAGE_LENGTH + 1);
+        assert_eq!(
+            problems(&[("WELCOME_MESSAGE", &message)]),
+            ["WELCOME_MESSAGE must be at most 2000 characters"]
+        );
+        // Characters are counted, not bytes
+        let message = "é".repeat(MAX_MESSAGE_LENGTH);
+        assert!(problems(&[("WELCOME_MESSAGE", &message)]).is_empty());
+    }
+
+    #[test]
+    fn event_webhook_must_be_http() {
+        assert_eq!(
+            problems(&[("EVENT_WEBHOOK_URL", "ftp://example.com/")]),
+            ["EVENT_WEBHOOK_URL must be an http:// or https:// URL"]
+        );
+        assert!(problems(&[("EVENT_WEBHOOK_URL", "https://example.com/")]).is_empty());
+    }
+
+    #[test]
+    fn urls_are_redacted() {
+        let config = mock::config(&[
+            ("EVENT_WEBHOOK_URL", "https://example.com/hooks/secret"),
+            ("GATEWAY_QUEUE_URL", "http://queue.local/?key=secret"),
+        ]);
+        let debug = format!("{config:?}");
+        assert!(!debug.contains("secret"));
+        assert_eq!(
+            config.event_webhook_url.as_ref().map(SecretUrl::host),
+            Some("example.com")
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Classification of the channels that threads are created in.
+
+use std::str::FromStr;
+
+use twilight_model::{
+    channel::{permission_overwrite::PermissionOverwriteType, Channel, ChannelType},
+    guild::Permissions,
+    id::{marker::GuildMarker, Id},
+};
+
+use crate::{reactions::Emoji, Config};
+
+/// What the bot knows about a channel that threads are created in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParentChannel {
+    pub guild_id: Option<Id<GuildMarker>>,
+    /// Whether threads created in this channel should be reacted to
+    pub reactable: bool,
+    /// Whether `@everyone` may not post in this channel
+    pub read_only: bool,
+    /// The forum's own default reaction, used instead of the configured emoji
+    pub default_reaction: Option<Emoji>,
+}
+
+impl ParentChannel {
+    pub fn new(config: &Config, channel: &Channel) -> Self {
+        Self {
+            guild_id: channel.guild_id,
+            reactable: is_reactable_kind(config, channel.kind),
+            read_only: is_read_only(channel),
+            default_reaction: config
+                .use_forum_default_reaction
+                .then(|| default_reaction(channel))
+                .flatten(),
+        }
+    }
+}
+
+/// The emoji a forum adds to new posts by itself, if it's set up to.
+fn default_reaction(channel: &Channel) -> Option<Emoji> {
+    let reaction = channel.default_reaction_emoji.as_ref()?;
+    if let Some(id) = reaction.emoji_id {
+        return Some(Emoji::Custom { id, name: None });
+    }
+    let name = reaction
+        .emoji_name
+        .as_deref()
+        .filter(|name| !name.is_empty())?;
+    // Parsing normalizes the emoji the same way configured ones are
+    name.parse().ok()
+}
+
+/// Whether threads created in a channel of this kind should be reacted to.
+fn is_reactable_kind(config: &Config, kind: ChannelType) -> bool {
+    match kind {
+        ChannelType::GuildForum => true,
+        ChannelType::GuildAnnouncement => config.react_in_announcements,
+        ChannelType::Unknown(kind) => match config.on_unknown_channel_type {
+            UnknownChannelAction::Skip => false,
+            UnknownChannelAction::React => {
+                debug!(kind, "Treating unknown channel type as a forum");
+                true
+            }
+            UnknownChannelAction::Warn => {
+                warn!(kind, "Threads were created in a channel of an unknown type");
+                false
+            }
+        },
+        _ => false,
+    }
+}
+
+/// What to do with threads whose parent is a channel type XLR doesn't know,
+/// such as types Discord added after this version was released.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnknownChannelAction {
+    /// Don't react, without logging
+    #[default]
+    Skip,
+    /// React as if the channel were a forum
+    React,
+    /// Don't react, but log a warning so the new type gets noticed
+    Warn,
+}
+
+impl FromStr for UnknownChannelAction {
+    type Err = UnknownChannelActionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(Self::Skip),
+            "react" => Ok(Self::React),
+            "warn" => Ok(Self::Warn),
+            _ => Err(UnknownChannelActionParseError(s.to_owned())),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown channel type action `{0}`, expected skip, react or warn")]
+pub struct UnknownChannelActionParseError(String);
+
+/// A channel is read-only if its `@everyone` overwrite denies sending
+/// messages, which for forums also means members can't create posts.
+fn is_read_only(channel: &Channel) -> bool {
+    let Some(guild_id) = channel.guild_id else {
+        return false;
+    };
+    channel
+        .permission_overwrites
+        .iter()
+        .flatten()
+        .any(|overwrite| {
+            overwrite.kind == PermissionOverwriteType::Role
+                && overwrite.id == guild_id.cast()
+                && overwrite.deny.contains(Permissions::SEND_MESSAGES)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::{self, GUILD};
+
+    fn overwrite(id: u64, deny: Permissions) -> serde_json::Value {
+        json!({
+            "id": id.to_string(),
+            "type": 0,
+            "allow": "0",
+            "deny": deny.bits().to_string(),
+        })
+    }
+
+    #[test]
+    fn everyone_denied_sending_is_read_only() {
+        let forum = mock::forum(json!({
+            "permission_overwrites": [overwrite(GUILD, Permissions::SEND_MESSAGES)],
+        }));
+        assert!(is_read_only(&forum));
+    }
+
+    #[test]
+    fn other_overwrites_are_not_read_only() {
+        let forum = mock::forum(json!({
+            "permission_overwrites": [
+                // A role other than @everyone
+                overwrite(GUILD + 100, Permissions::SEND_MESSAGES),
+                overwrite(GUILD, Permissions::ADD_REACTIONS),
+            ],
+        }));
+        assert!(!is_read_only(&forum));
+        assert!(!is_read_only(&mock::forum(json!({}))));
+    }
+
+    #[test]
+    fn forum_default_reaction_is_used() {
+        let config = mock::config(&[]);
+        let unicode = mock::forum(json!({
+            "default_reaction_emoji": {"emoji_id": null, "emoji_name": "👍"},
+        }));
+        assert_eq!(
+            ParentChannel::new(&config, &unicode).default_reaction,
+            Some(Emoji::Unicode("👍".to_owned()))
+        );
+        let custom = mock::forum(json!({
+            "default_reaction_emoji": {"emoji_id": "123", "emoji_name": null},
+        }));
+        assert_eq!(
+            ParentChannel::new(&config, &custom).default_reaction,
+            Some(Emoji::Custom {
+                id: Id::new(123),
+                name: None
+            })
+        );
+    }
+
+    #[test]
+    fn forum_without_default_reaction() {
+        let config = mock::config(&[]);
+        let forum = mock::forum(json!({}));
+        assert_eq!(ParentChannel::new(&config, &forum).default_reaction, None);
+        let empty = mock::forum(json!({
+            "default_reaction_emoji": {"emoji_id": null, "emoji_name": ""},
+        }));
+        assert_eq!(ParentChannel::new(&config, &empty).default_reaction, None);
+    }
+
+    #[test]
+    fn forum_default_reaction_can_be_ignored() {
+        let config = mock::config(&[("USE_FORUM_DEFAULT_REACTION", "false")]);
+        let forum = mock::forum(json!({
+            "default_reaction_emoji": {"emoji_id": null, "emoji_name": "👍"},
+        }));
+        assert_eq!(ParentChannel::new(&config, &forum).default_reaction, None);
+    }
+}
@@ -0,0 +1,1639 @@
+use std::{
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::RwLock;
+use tokio::sync::oneshot::Receiver;
+use twilight_gateway::Shard;
+use twilight_http::error::ErrorType;
+use twilight_model::{
+    channel::{message::MessageFlags, thread::AutoArchiveDuration, ChannelType, Message},
+    gateway::{
+        event::Event,
+        payload::incoming::{ThreadCreate, ThreadUpdate},
+        CloseFrame,
+    },
+    id::{
+        marker::{
+            ApplicationMarker, ChannelMarker, GuildMarker, MessageMarker, RoleMarker, UserMarker,
+        },
+        Id,
+    },
+};
+
+#[macro_use]
+extern crate tracing;
+
+pub mod api;
+mod burst;
+mod cache;
+mod commands;
+pub mod config;
+pub mod forums;
+pub mod logging;
+pub mod metrics;
+#[cfg(test)]
+mod mock;
+pub mod publish;
+pub mod queue;
+pub mod reactions;
+pub mod removal;
+pub mod rescan;
+mod retry;
+mod safe_mode;
+pub mod session;
+mod systemd;
+mod template;
+
+pub use api::DiscordApi;
+use burst::BurstDetector;
+use cache::LruMap;
+pub use config::Config;
+use forums::ParentChannel;
+use metrics::Metrics;
+use publish::{Publisher, ThreadEvent};
+use reactions::{Emoji, KeywordRule, ReactionTarget};
+use removal::ReactionRemovals;
+use retry::RetryBudget;
+use safe_mode::SafeMode;
+
+pub async fn event_loop(state: &AppState, mut shard: Shard, mut shutdown_r: Receiver<()>) {
+    let mut watchdog = systemd::Watchdog::from_env();
+    loop {
+        #[allow(clippy::redundant_pub_crate)]
+        let next = tokio::select! {
+            v = shard.next_event() => v,
+            () = watchdog.tick() => continue,
+            _ = &mut shutdown_r => break,
+        };
+        trace!(?next, "got new event");
+        let event = match next {
+            Ok(event) => event,
+            Err(source) => {
+                error!(?source, "error receiving event");
+                if source.is_fatal() {
+                    break;
+                }
+                continue;
+            }
+        };
+        handle_event(state, event, shard.latency().average()).await;
+    }
+    systemd::notify("STOPPING=1");
+    // Removals scheduled since the last check would otherwise be forgotten
+    state.removals.flush().await;
+    state.metrics.log_final_summary();
+    let session = shard.session().cloned();
+    if let Some(path) = &state.config.session_file {
+        // Closing with the resume code keeps the session valid on Discord's side
+        let _ = shard.close(CloseFrame::RESUME).await;
+        if let Some(session) = session {
+            session::save(path, &session);
+        }
+    } else {
+        let _ = shard.close(CloseFrame::NORMAL).await;
+    }
+}
+
+/// Act on a single gateway event.
+///
+/// `latency` is the shard's average heartbeat latency, for commands that report it.
+pub async fn handle_event(state: &AppState, event: Event, latency: Option<Duration>) {
+    if matches!(event, Event::Ready(_) | Event::Resumed) {
+        systemd::notify("READY=1");
+    }
+    if let Event::Ready(ready) = &event {
+        // The bot's ids never change, so only the first Ready matters
+        let _ = state.bot_id.set(ready.user.id);
+        if state.application_id.set(ready.application.id).is_ok() {
+            // Registering retries on ratelimits, which shouldn't hold up events
+            let state = state.clone();
+            let application_id = ready.application.id;
+            tokio::spawn(async move { commands::register(&state, application_id).await });
+        }
+    }
+    if let Event::InteractionCreate(interaction) = &event {
+        if let Err(source) = commands::handle(state, interaction, latency).await {
+            error!(?source, "failed to handle interaction");
+        }
+    }
+    match &event {
+        // Only refresh channels we already know about, so the cache
+        // doesn't fill up with every channel in every guild
+        Event::ChannelUpdate(channel) => {
+            // The update may well be the permission change that lets us in
+            state.no_access.write().remove(&channel.id);
+            if let Some(cached) = state.forums.write().get_mut(&channel.id) {
+                *cached = ParentChannel::new(&state.config, channel);
+            }
+        }
+        Event::ChannelDelete(channel) => {
+            state.forums.write().remove(&channel.id);
+            state.no_access.write().remove(&channel.id);
+        }
+        Event::ThreadDelete(thread) => {
+            state.applied.write().remove(&thread.id);
+        }
+        // Unavailable guilds are in an outage, the bot hasn't left them
+        Event::GuildDelete(guild) if !guild.unavailable => {
+            let guild_id = Some(guild.id);
+            let channels = purge(&state.forums, |_, parent| parent.guild_id != guild_id);
+            let no_access = purge(&state.no_access, |_, (id, _)| *id != guild_id);
+            let threads = purge(&state.applied, |_, applied| applied.guild_id != guild_id);
+            let members = purge(&state.member_roles, |(id, _), _| *id != guild.id);
+            info!(
+                guild = guild.id.get(),
+                channels,
+                no_access,
+                threads,
+                members,
+                "left guild, purged what was cached about it"
+            );
+        }
+        _ => {}
+    }
+    if let Event::ThreadUpdate(thread) = event {
+        if !state.config.keywords_on_edit || !state.safe_mode.allow() {
+            return;
+        }
+        if state.config.waits_between_steps() {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let result = on_thread_update(&state, thread).await;
+                wrap_result(&state, result);
+            });
+        } else {
+            wrap_result(state, on_thread_update(state, thread).await);
+        }
+        return;
+    }
+    if let Event::ThreadCreate(thread) = event {
+        // Threads the bot is added to also come through here, and say nothing
+        // about lag, since they can be of any age
+        if thread.newly_created == Some(true) {
+            state
+                .metrics
+                .event_lag(creation_lag(thread.id), state.config.backlog_warn);
+        }
+        if !state.safe_mode.allow() {
+            debug!(
+                thread = thread.id.get(),
+                "Skipping thread because safe mode is active"
+            );
+            return;
+        }
+        if state.config.waits_between_steps() {
+            // Don't hold up the event loop while waiting between steps
+            let state = state.clone();
+            tokio::spawn(async move {
+                let _handler = state.metrics.start_handler();
+                let result = on_thread_create(&state, thread).await;
+                wrap_result(&state, result);
+            });
+        } else {
+            let _handler = state.metrics.start_handler();
+            wrap_result(state, on_thread_create(state, thread).await);
+        }
+    }
+}
+
+/// Keep only the entries of `cache` that `keep` returns true for, returning how many were removed.
+fn purge<K: Clone + Eq + Hash, V>(
+    cache: &RwLock<LruMap<K, V>>,
+    keep: impl FnMut(&K, &V) -> bool,
+) -> usize {
+    let mut cache = cache.write();
+    let before = cache.len();
+    cache.retain(keep);
+    before - cache.len()
+}
+
+/// How long ago a channel was created, read from its id.
+///
+/// A forum post's id is its starter message's, so for threads this is how long
+/// the event took to reach the bot and get through the event loop.
+fn creation_lag(id: Id<ChannelMarker>) -> Duration {
+    let created = UNIX_EPOCH + Duration::from_millis((id.get() >> 22) + DISCORD_EPOCH_MILLIS);
+    // Clock skew can put the creation ahead of the local clock
+    SystemTime::now()
+        .duration_since(created)
+        .unwrap_or(Duration::ZERO)
+}
+
+/// Log and count a handler's error, or tell safe mode it succeeded.
+///
+/// Only whole handlers count, since a handler whose reads work but whose
+/// reactions fail is still failing.
+fn wrap_result<T>(state: &AppState, result: Result<T, Error>) {
+    match result {
+        Ok(_) => state.safe_mode.record_success(),
+        Err(source) => {
+            error!(?source, "encountered an error");
+            state.metrics.error(source.kind(), source.status());
+            state.safe_mode.record_error();
+        }
+    }
+}
+
+async fn on_thread_create(state: &AppState, thread: Box<ThreadCreate>) -> Result<(), Error> {
+    if state.maintenance.load(Ordering::Relaxed) {
+        info!(
+            thread = thread.id.get(),
+            "Skipping thread because maintenance mode is active"
+        );
+        return Ok(());
+    }
+    if !is_guild_allowed(&state.config, thread.guild_id) {
+        debug!(
+            guild = thread.guild_id.map(Id::get),
+            thread = thread.id.get(),
+            "Skipping thread because its guild is not allowlisted"
+        );
+        return Ok(());
+    }
+    // Authors often fix up the title and tags right after posting, so wait
+    // and read the thread again to decide based on the latest version.
+    let thread = if let Some(grace) = state.config.reaction_grace {
+        tokio::time::sleep(grace).await;
+        match state
+            .timed("channel", state.client.channel(thread.id))
+            .await
+        {
+            Ok(channel) => Box::new(ThreadCreate(channel)),
+            Err(source) if source.status() == Some(404) => {
+                debug!(
+                    thread = thread.id.get(),
+                    "Skipping thread because it was deleted during the grace period"
+                );
+                return Ok(());
+            }
+            Err(source) => return Err(source),
+        }
+    } else {
+        thread
+    };
+    let title_length = thread
+        .name
+        .as_deref()
+        .map_or(0, |name| name.chars().count());
+    if title_length < state.config.min_title_length {
+        debug!(
+            thread = thread.id.get(),
+            title_length, "Skipping thread because its title is too short"
+        );
+        return Ok(());
+    }
+    let parent = thread.parent_id.ok_or(Error::NoThreadParentId)?;
+    let Some(parent_channel) = classify_parent(state, thread.guild_id, parent).await? else {
+        debug!(
+            parent = parent.get(),
+            thread = thread.id.get(),
+            "Skipping thread because the bot can't view its parent"
+        );
+        return Ok(());
+    };
+    if !parent_channel.reactable {
+        debug!(
+            parent = parent.get(),
+            thread = thread.id.get(),
+            "Skipping channel because parent was not a forum"
+        );
+        return Ok(());
+    }
+    if !state.burst.allow(parent) {
+        debug!(
+            parent = parent.get(),
+            thread = thread.id.get(),
+            "Skipping thread because reactions in its forum are paused after a burst"
+        );
+        return Ok(());
+    }
+    let locked = (parent_channel.read_only && state.config.lock_read_only_forums)
+        || thread
+            .thread_metadata
+            .as_ref()
+            .is_some_and(|meta| meta.archived || meta.locked);
+    if locked && state.config.locked_emoji.is_empty() {
+        debug!(
+            parent = parent.get(),
+            thread = thread.id.get(),
+            "Skipping thread because it or its forum is locked"
+        );
+        return Ok(());
+    }
+    // Forum posts keep their starter message inside the thread, but announcement
+    // threads are started from a message in the parent channel. Either way the
+    // starter message shares the thread's id.
+    let starter_channel_id = if thread.kind == ChannelType::AnnouncementThread {
+        parent
+    } else {
+        thread.id
+    };
+    let (channel_id, message_id, message) = match state.config.reaction_target {
+        ReactionTarget::Starter => {
+            let message_id = thread.id.cast();
+            let message = if state.config.require_starter_message
+                || state.config.skip_existing_reactions
+                || state.config.checks_crossposts()
+                || state.config.skip_webhook_threads
+            {
+                fetch_message(state, starter_channel_id, message_id).await?
+            } else {
+                None
+            };
+            if state.config.require_starter_message && message.is_none() {
+                debug!(
+                    thread = thread.id.get(),
+                    "Skipping thread because its starter message does not exist"
+                );
+                return Ok(());
+            }
+            (starter_channel_id, message_id, message)
+        }
+        ReactionTarget::Latest => {
+            let latest = state
+                .timed("channel_messages", state.client.latest_message(thread.id))
+                .await?;
+            let Some(message) = latest else {
+                debug!(
+                    thread = thread.id.get(),
+                    "Skipping thread because it has no messages"
+                );
+                return Ok(());
+            };
+            (thread.id, message.id, Some(message))
+        }
+    };
+    // The flags say whether the message was relayed from a followed announcement channel
+    let crosspost = message.as_ref().is_some_and(|message| {
+        message
+            .flags
+            .is_some_and(|flags| flags.contains(MessageFlags::IS_CROSSPOST))
+    });
+    if crosspost && state.config.skip_crossposts {
+        debug!(
+            thread = thread.id.get(),
+            "Skipping thread because its message is a crosspost"
+        );
+        return Ok(());
+    }
+    if state.config.skip_webhook_threads
+        && message
+            .as_ref()
+            .is_some_and(|message| message.webhook_id.is_some())
+    {
+        debug!(
+            thread = thread.id.get(),
+            "Skipping thread because its message was posted by a webhook"
+        );
+        return Ok(());
+    }
+    let role_emoji = if locked || state.config.role_emoji.is_empty() {
+        Vec::new()
+    } else {
+        author_role_emoji(state, &thread).await?
+    };
+    let default_emoji = if locked {
+        &state.config.locked_emoji
+    } else if crosspost && !state.config.crosspost_emoji.is_empty() {
+        &state.config.crosspost_emoji
+    } else if is_bot_thread(state, &thread) && !state.config.bot_thread_emoji.is_empty() {
+        &state.config.bot_thread_emoji
+    } else if !role_emoji.is_empty() {
+        &role_emoji
+    } else {
+        state
+            .config
+            .reaction_emoji_for(thread.guild_id, parent_channel.default_reaction.as_ref())
+    };
+    let keyword_emoji = if locked {
+        Vec::new()
+    } else {
+        KeywordRule::matching(
+            &state.config.keyword_emoji,
+            thread.name.as_deref().unwrap_or_default(),
+        )
+    };
+    let mut emoji = reactions::resolve(
+        [default_emoji.iter(), keyword_emoji.iter()],
+        state.config.max_reactions,
+    );
+    // Emoji already on the message count as applied too, so work this out before filtering
+    let applied = (state.config.keywords_on_edit && !locked).then(|| Applied {
+        guild_id: thread.guild_id,
+        channel_id,
+        message_id,
+        emoji: emoji.iter().map(|emoji| (*emoji).clone()).collect(),
+    });
+    if state.config.skip_existing_reactions {
+        if let Some(message) = &message {
+            emoji.retain(|emoji| {
+                !message
+                    .reactions
+                    .iter()
+                    .any(|reaction| emoji.matches(&reaction.emoji))
+            });
+        }
+    }
+    let reacted = react_to_thread(state, channel_id, message_id, &emoji).await?;
+    // The archive duration belongs to the thread rather than the message, so
+    // it's set even when reacting didn't work out
+    set_auto_archive_duration(state, &thread, parent).await?;
+    if !reacted {
+        return Ok(());
+    }
+    if let Some(applied) = applied {
+        state.applied.write().insert(thread.id, applied);
+    }
+    if emoji.is_empty() {
+        // Most likely the thread was handled already, say before a restart,
+        // so it has been welcomed and published too
+        debug!(
+            thread = thread.id.get(),
+            "Not reacting because the message already has every reaction"
+        );
+        return Ok(());
+    }
+    post_welcome_message(state, &thread, parent).await?;
+    let event = ThreadEvent {
+        guild: thread.guild_id,
+        forum: parent,
+        thread: thread.id,
+        emoji: emoji.iter().map(ToString::to_string).collect(),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs()),
+    };
+    publish(state, event);
+    Ok(())
+}
+
+/// Send a thread event to the configured publisher in the background, so
+/// a slow or failing endpoint can't hold up thread handling.
+fn publish(state: &AppState, event: ThreadEvent) {
+    let Some(publisher) = state.publisher.clone() else {
+        return;
+    };
+    tokio::spawn(async move {
+        if let Err(source) = publisher.publish(&event).await {
+            warn!(
+                ?source,
+                thread = event.thread.get(),
+                "failed to publish thread event"
+            );
+        }
+    });
+}
+
+/// React to a message with each emoji in order, pausing between them if configured.
+///
+/// Every path that adds reactions goes through here. Returns `false` if the
+/// message was deleted or the bot isn't allowed to react to it, which callers
+/// should treat as a reason to stop rather than as an error.
+async fn react_to_thread(
+    state: &AppState,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    emoji: &[&Emoji],
+) -> Result<bool, Error> {
+    for (i, emoji) in emoji.iter().enumerate() {
+        if let Some(interval) = state.config.reaction_interval.filter(|_| i > 0) {
+            tokio::time::sleep(interval).await;
+        }
+        let result = retry::retry(state, || {
+            let request = state.client.create_reaction(channel_id, message_id, emoji);
+            state.timed("create_reaction", request)
+        })
+        .await;
+        match result {
+            Ok(()) => {
+                state.metrics.reaction_added();
+                state.removals.schedule(channel_id, message_id, emoji);
+            }
+            Err(source) if source.status() == Some(404) => {
+                debug!(
+                    channel = channel_id.get(),
+                    message = message_id.get(),
+                    "Stopped reacting because the message was deleted"
+                );
+                return Ok(false);
+            }
+            // Missing permissions in one channel is a setup problem, not a reason for safe mode
+            Err(source) if source.status() == Some(403) => {
+                warn!(
+                    channel = channel_id.get(),
+                    message = message_id.get(),
+                    "Not allowed to react, check the bot's permissions in this channel"
+                );
+                return Ok(false);
+            }
+            Err(source) => return Err(source),
+        }
+    }
+    Ok(true)
+}
+
+/// Add emoji for keyword rules that an edited thread title matches now but didn't before.
+///
+/// Only threads XLR reacted to in this run are considered, since it knows
+/// which emoji those already have.
+async fn on_thread_update(state: &AppState, thread: Box<ThreadUpdate>) -> Result<(), Error> {
+    if state.maintenance.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let Some(applied) = state.applied.read().get(&thread.id).cloned() else {
+        return Ok(());
+    };
+    let matching = KeywordRule::matching(
+        &state.config.keyword_emoji,
+        thread.name.as_deref().unwrap_or_default(),
+    );
+    let room = state
+        .config
+        .max_reactions
+        .saturating_sub(applied.emoji.len());
+    let new: Vec<Emoji> = matching
+        .into_iter()
+        .filter(|emoji| !applied.emoji.contains(emoji))
+        .take(room)
+        .collect();
+    if new.is_empty() {
+        return Ok(());
+    }
+    debug!(
+        thread = thread.id.get(),
+        added = new.len(),
+        "Thread edit matched new keywords"
+    );
+    let emoji: Vec<&Emoji> = new.iter().collect();
+    if !react_to_thread(state, applied.channel_id, applied.message_id, &emoji).await? {
+        state.applied.write().remove(&thread.id);
+        return Ok(());
+    }
+    if let Some(applied) = state.applied.write().get_mut(&thread.id) {
+        applied.emoji.extend(new);
+    }
+    Ok(())
+}
+
+/// Post the configured welcome message in a new thread, if there is one.
+async fn post_welcome_message(
+    state: &AppState,
+    thread: &ThreadCreate,
+    parent: Id<ChannelMarker>,
+) -> Result<(), Error> {
+    let Some(welcome) = &state.config.welcome_message else {
+        return Ok(());
+    };
+    let author = thread
+        .owner_id
+        .map_or_else(String::new, |owner| format!("<@{owner}>"));
+    let title = template::escape(thread.name.as_deref().unwrap_or_default());
+    let forum = format!("<#{parent}>");
+    let guild = thread
+        .guild_id
+        .map_or_else(String::new, |guild| guild.to_string());
+    let mut content = template::render(
+        welcome,
+        &[
+            ("author", &author),
+            ("thread", &title),
+            ("forum", &forum),
+            ("guild", &guild),
+        ],
+    );
+    // Long titles can push a template that fits over Discord's limit
+    if template::truncate(&mut content, config::MAX_MESSAGE_LENGTH) {
+        warn!(
+            thread = thread.id.get(),
+            "welcome message was too long once filled in, so it was cut short"
+        );
+    }
+    if let Some(typing) = state.config.welcome_typing {
+        // Only cosmetic, so a failure shouldn't stop the message
+        let request = state.client.trigger_typing(thread.id);
+        if let Err(source) = state.timed("typing", request).await {
+            debug!(
+                ?source,
+                thread = thread.id.get(),
+                "failed to trigger typing"
+            );
+        }
+        tokio::time::sleep(typing).await;
+    }
+    let request = state.client.create_message(thread.id, &content);
+    match state.timed("create_message", request).await {
+        Ok(_) => Ok(()),
+        Err(source) if source.status() == Some(404) => {
+            debug!(
+                thread = thread.id.get(),
+                "Skipping welcome message because the thread was deleted"
+            );
+            Ok(())
+        }
+        // Like with reactions, a missing permission shouldn't trip safe mode
+        Err(source) if source.status() == Some(403) => {
+            warn!(
+                thread = thread.id.get(),
+                "Not allowed to post the welcome message, check the bot's permissions in this forum"
+            );
+            Ok(())
+        }
+        Err(source) => Err(source),
+    }
+}
+
+/// Give a new thread the configured auto-archive duration, if there is one.
+async fn set_auto_archive_duration(
+    state: &AppState,
+    thread: &ThreadCreate,
+    parent: Id<ChannelMarker>,
+) -> Result<(), Error> {
+    let Some(minutes) = state
+        .config
+        .forum_auto_archive_minutes
+        .get(&parent)
+        .copied()
+        .or(state.config.auto_archive_minutes)
+    else {
+        return Ok(());
+    };
+    let duration = AutoArchiveDuration::from(minutes);
+    if let AutoArchiveDuration::Unknown { value } = duration {
+        warn!(
+            minutes = value,
+            parent = parent.get(),
+            "Invalid auto-archive duration configured, expected 60, 1440, 4320 or 10080"
+        );
+        return Ok(());
+    }
+    let current = thread
+        .thread_metadata
+        .as_ref()
+        .map(|meta| meta.auto_archive_duration);
+    if current == Some(duration) {
+        return Ok(());
+    }
+    let request = state.client.set_auto_archive_duration(thread.id, duration);
+    match state.timed("update_thread", request).await {
+        Ok(()) => Ok(()),
+        Err(source) if source.status() == Some(404) => {
+            debug!(
+                thread = thread.id.get(),
+                "Skipping auto-archive duration because the thread was deleted"
+            );
+            Ok(())
+        }
+        // Needs Manage Threads, which is a setup problem rather than a reason for safe mode
+        Err(source) if source.status() == Some(403) => {
+            warn!(
+                thread = thread.id.get(),
+                "Not allowed to set the auto-archive duration, check the bot's Manage Threads permission"
+            );
+            Ok(())
+        }
+        Err(source) => Err(source),
+    }
+}
+
+/// Fetch a message, or `None` if it doesn't exist.
+async fn fetch_message(
+    state: &AppState,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+) -> Result<Option<Message>, Error> {
+    match state
+        .timed("message", state.client.message(channel_id, message_id))
+        .await
+    {
+        Ok(message) => Ok(Some(message)),
+        Err(source) if source.status() == Some(404) => Ok(None),
+        Err(source) => Err(source),
+    }
+}
+
+/// An empty allowlist allows every guild.
+fn is_guild_allowed(config: &Config, guild_id: Option<Id<GuildMarker>>) -> bool {
+    config.guild_allowlist.is_empty()
+        || guild_id.is_some_and(|guild_id| config.guild_allowlist.contains(&guild_id))
+}
+
+/// Whether the thread was started by this bot or one of the configured bot authors.
+fn is_bot_thread(state: &AppState, thread: &ThreadCreate) -> bool {
+    let Some(owner) = thread.owner_id else {
+        return false;
+    };
+    state.bot_id.get() == Some(&owner) || state.config.bot_authors.contains(&owner)
+}
+
+/// The configured emoji for each of the thread author's roles, in the order
+/// Discord lists the roles.
+async fn author_role_emoji(state: &AppState, thread: &ThreadCreate) -> Result<Vec<Emoji>, Error> {
+    let (Some(guild_id), Some(owner)) = (thread.guild_id, thread.owner_id) else {
+        return Ok(Vec::new());
+    };
+    let Some(roles) = member_roles(state, guild_id, owner).await? else {
+        return Ok(Vec::new());
+    };
+    Ok(roles
+        .iter()
+        .filter_map(|role| state.config.role_emoji.get(role))
+        .cloned()
+        .collect())
+}
+
+/// A member's roles, reusing recent fetches for `member_cache_ttl`.
+///
+/// Returns `None` if the user isn't in the guild anymore.
+async fn member_roles(
+    state: &AppState,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<Option<Vec<Id<RoleMarker>>>, Error> {
+    if let Some((fetched_at, roles)) = state.member_roles.read().get(&(guild_id, user_id)) {
+        if fetched_at.elapsed() < state.config.member_cache_ttl {
+            return Ok(Some(roles.clone()));
+        }
+    }
+    let member = match state
+        .timed("guild_member", state.client.guild_member(guild_id, user_id))
+        .await
+    {
+        Ok(member) => member,
+        Err(source) if source.status() == Some(404) => return Ok(None),
+        Err(source) => return Err(source),
+    };
+    let mut cache = state.member_roles.write();
+    let ttl = state.config.member_cache_ttl;
+    cache.retain(|_, (fetched_at, _)| fetched_at.elapsed() < ttl);
+    cache.insert(
+        (guild_id, user_id),
+        (tokio::time::Instant::now(), member.roles.clone()),
+    );
+    Ok(Some(member.roles))
+}
+
+/// Look up how to treat threads in `parent`, fetching it on a cache miss.
+///
+/// Returns `None` if the bot isn't allowed to view the parent. That is
+/// remembered for `no_access_cooldown` so it isn't asked again for every thread.
+async fn classify_parent(
+    state: &AppState,
+    guild_id: Option<Id<GuildMarker>>,
+    parent: Id<ChannelMarker>,
+) -> Result<Option<ParentChannel>, Error> {
+    if let Some(parent_channel) = state.forums.read().get(&parent) {
+        state.metrics.cache_hit();
+        return Ok(Some(parent_channel.clone()));
+    }
+    if let Some((_, denied_at)) = state.no_access.read().get(&parent) {
+        if denied_at.elapsed() < state.config.no_access_cooldown {
+            return Ok(None);
+        }
+    }
+    state.metrics.cache_miss();
+    let result = retry::retry(state, || {
+        state.timed("channel", state.client.channel(parent))
+    })
+    .await;
+    let channel = match result {
+        Ok(channel) => channel,
+        Err(source) if source.status() == Some(403) => {
+            info!(
+                parent = parent.get(),
+                "bot can't view thread parent, skipping its threads for a while"
+            );
+            state
+                .no_access
+                .write()
+                .insert(parent, (guild_id, Instant::now()));
+            return Ok(None);
+        }
+        Err(source) => return Err(source),
+    };
+    state.no_access.write().remove(&parent);
+    let parent_channel = ParentChannel::new(&state.config, &channel);
+    state.forums.write().insert(parent, parent_channel.clone());
+    Ok(Some(parent_channel))
+}
+
+/// The HTTP status code Discord responded with, if the request got that far.
+fn response_status(source: &twilight_http::Error) -> Option<u16> {
+    match source.kind() {
+        ErrorType::Response { status, .. } => Some(status.get()),
+        _ => None,
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("twilight-http error: {0}")]
+    DiscordApi(#[from] twilight_http::Error),
+    #[error("twilight-http deserializer error: {0}")]
+    BodyDeserialize(#[from] twilight_http::response::DeserializeBodyError),
+    #[error("twilight-validate error: {0}")]
+    Validation(#[from] twilight_validate::request::ValidationError),
+    #[error("twilight-validate command error: {0}")]
+    CommandValidation(#[from] twilight_validate::command::CommandValidationError),
+    #[error("twilight-validate message error: {0}")]
+    MessageValidation(#[from] twilight_validate::message::MessageValidationError),
+    #[error("Discord did not send a parent channel ID, are you sure this is a thread?")]
+    NoThreadParentId,
+    /// A failure scripted by the test mock, as if Discord responded with this status
+    #[cfg(test)]
+    #[error("mock response with status {0}")]
+    MockStatus(u16),
+}
+
+impl Error {
+    /// Short, stable name for this kind of error, used as a metrics label.
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::DiscordApi(_) => "discord_api",
+            Self::BodyDeserialize(_) => "body_deserialize",
+            Self::Validation(_) => "validation",
+            Self::CommandValidation(_) => "command_validation",
+            Self::MessageValidation(_) => "message_validation",
+            Self::NoThreadParentId => "no_thread_parent_id",
+            #[cfg(test)]
+            Self::MockStatus(_) => "mock",
+        }
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::DiscordApi(source) => response_status(source),
+            #[cfg(test)]
+            Self::MockStatus(status) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+/// Shared state for the bot. The `Debug` impl leaves out the HTTP client.
+pub struct InnerAppState {
+    client: Box<dyn DiscordApi>,
+    config: Config,
+    forums: RwLock<LruMap<Id<ChannelMarker>, ParentChannel>>,
+    /// Parent channels the bot got a 403 for
+    no_access: RwLock<NoAccess>,
+    /// Emoji XLR added to each thread, for adding keyword matches after edits
+    applied: RwLock<LruMap<Id<ChannelMarker>, Applied>>,
+    /// Recently fetched roles of thread authors, and when they were fetched
+    member_roles: RwLock<MemberRoles>,
+    metrics: Metrics,
+    safe_mode: SafeMode,
+    burst: BurstDetector,
+    removals: ReactionRemovals,
+    retry_budget: RetryBudget,
+    publisher: Option<Arc<dyn Publisher>>,
+    /// Set by operators to stop acting on threads while staying connected
+    maintenance: AtomicBool,
+    bot_id: OnceLock<Id<UserMarker>>,
+    application_id: OnceLock<Id<ApplicationMarker>>,
+}
+
+impl std::fmt::Debug for InnerAppState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InnerAppState")
+            .field("config", &self.config)
+            .field("forums", &*self.forums.read())
+            .field("no_access", &*self.no_access.read())
+            .field("applied", &*self.applied.read())
+            .field("member_roles", &*self.member_roles.read())
+            .field("metrics", &self.metrics)
+            .field("safe_mode", &self.safe_mode)
+            .field("burst", &self.burst)
+            .field("removals", &self.removals)
+            .field("retry_budget", &self.retry_budget)
+            .field("publisher", &self.publisher.is_some())
+            .field("maintenance", &self.maintenance)
+            .field("bot_id", &self.bot_id)
+            .field("application_id", &self.application_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl InnerAppState {
+    pub fn new(client: impl DiscordApi + 'static, config: Config) -> Self {
+        let safe_mode = SafeMode::new(&config);
+        let burst = BurstDetector::new(&config);
+        let removals = ReactionRemovals::new(&config);
+        let retry_budget = RetryBudget::new(&config);
+        Self {
+            client: Box::new(client),
+            forums: RwLock::new(LruMap::new("forums", config.forum_cache_max)),
+            no_access: RwLock::new(LruMap::new("no_access", config.forum_cache_max)),
+            applied: RwLock::new(LruMap::new("applied", Some(APPLIED_CACHE_MAX))),
+            member_roles: RwLock::new(LruMap::new("member_roles", config.member_cache_max)),
+            metrics: Metrics::default(),
+            safe_mode,
+            burst,
+            removals,
+            retry_budget,
+            publisher: None,
+            maintenance: AtomicBool::new(false),
+            bot_id: OnceLock::new(),
+            application_id: OnceLock::new(),
+            config,
+        }
+    }
+
+    /// Publish an event for every thread that's handled.
+    #[must_use]
+    pub fn with_publisher(mut self, publisher: impl Publisher + 'static) -> Self {
+        self.publisher = Some(Arc::new(publisher));
+        self
+    }
+
+    /// Pre-populate the forum cache with already classified channels.
+    #[must_use]
+    pub fn with_forums(
+        self,
+        forums: impl IntoIterator<Item = (Id<ChannelMarker>, ParentChannel)>,
+    ) -> Self {
+        self.forums.write().extend(forums);
+        self
+    }
+
+    /// How many entries each cache holds, by name, with the forum cache first.
+    pub fn cache_sizes(&self) -> [(&'static str, usize); 4] {
+        fn size<K: Clone + Eq + Hash, V>(cache: &RwLock<LruMap<K, V>>) -> (&'static str, usize) {
+            let cache = cache.read();
+            (cache.name(), cache.len())
+        }
+        [
+            size(&self.forums),
+            size(&self.no_access),
+            size(&self.applied),
+            size(&self.member_roles),
+        ]
+    }
+
+    pub const fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Whether the bot's reactions are removed after a voting period.
+    pub const fn removes_reactions(&self) -> bool {
+        self.removals.is_enabled()
+    }
+
+    /// Await a Discord API request, recording it with [`Metrics::timed`].
+    ///
+    /// Every request goes through here, so they're all accounted for the same way.
+    pub async fn timed<T>(
+        &self,
+        route: &'static str,
+        request: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        self.metrics.timed(route, request).await
+    }
+
+    /// Turn maintenance mode on or off, returning whether it is now on.
+    pub fn toggle_maintenance(&self) -> bool {
+        let enabled = !self.maintenance.fetch_xor(true, Ordering::Relaxed);
+        if enabled {
+            warn!("entered maintenance mode, ignoring new threads");
+        } else {
+            info!("left maintenance mode");
+        }
+        enabled
+    }
+}
+
+pub type AppState = Arc<InnerAppState>;
+
+/// Milliseconds from the Unix epoch to the start of 2015, where snowflakes count from.
+const DISCORD_EPOCH_MILLIS: u64 = 1_420_070_400_000;
+
+/// Threads to remember the emoji of. Edits mostly happen right after posting,
+/// so old threads can be forgotten.
+const APPLIED_CACHE_MAX: usize = 10_000;
+
+/// The message XLR reacted to in a thread, and with what.
+#[derive(Clone, Debug)]
+struct Applied {
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    emoji: Vec<Emoji>,
+}
+
+/// The guild of each parent, so leaving it can forget them, and when access was denied.
+type NoAccess = LruMap<Id<ChannelMarker>, (Option<Id<GuildMarker>>, Instant)>;
+
+/// Timed with tokio's clock, so tests can skip ahead past the TTL.
+type MemberRoles =
+    LruMap<(Id<GuildMarker>, Id<UserMarker>), (tokio::time::Instant, Vec<Id<RoleMarker>>)>;
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use twilight_model::{gateway::payload::incoming::GuildDelete, guild::Permissions};
+
+    use super::*;
+    use crate::mock::{self, Call, MockApi, AUTHOR, FORUM, GUILD, THREAD};
+
+    fn thread_create(overrides: Value) -> Event {
+        Event::ThreadCreate(Box::new(ThreadCreate(mock::thread(overrides))))
+    }
+
+    fn thread_update(overrides: Value) -> Event {
+        Event::ThreadUpdate(Box::new(ThreadUpdate(mock::thread(overrides))))
+    }
+
+    /// A mock that knows the forum threads are created in.
+    fn forum_mock() -> MockApi {
+        let mock = MockApi::default();
+        mock.add_channel(mock::forum(json!({})));
+        mock
+    }
+
+    /// An announcement channel with a thread started from one of its messages.
+    fn announcement_mock() -> MockApi {
+        let mock = MockApi::default();
+        // 5 is an announcement channel
+        mock.add_channel(mock::forum(json!({"type": 5})));
+        mock
+    }
+
+    #[tokio::test]
+    async fn announcement_threads_are_skipped_by_default() {
+        let mock = announcement_mock();
+        let state = mock::state(&mock, &[]);
+        // 10 is an announcement thread
+        handle_event(&state, thread_create(json!({"type": 10})), None).await;
+        assert!(mock.reactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn announcement_threads_react_to_the_parent_message() {
+        let mock = announcement_mock();
+        let state = mock::state(&mock, &[("REACT_IN_ANNOUNCEMENTS", "true")]);
+        handle_event(&state, thread_create(json!({"type": 10})), None).await;
+        assert_eq!(
+            mock.reactions(),
+            vec![(Id::new(FORUM), Id::new(THREAD), "⬆️".parse().unwrap())]
+        );
+    }
+
+    #[tokio::test]
+    async fn thread_without_starter_message_is_skipped() {
+        let mock = forum_mock();
+        let state = mock::state(&mock, &[("REQUIRE_STARTER_MESSAGE", "true")]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.count("message"), 1);
+        assert!(mock.reactions().is_empty());
+        assert_eq!(state.metrics.errors_total(), 0);
+    }
+
+    #[tokio::test]
+    async fn thread_with_starter_message_is_reacted_to() {
+        let mock = forum_mock();
+        mock.add_message(mock::message(json!({})));
+        let state = mock::state(&mock, &[("REQUIRE_STARTER_MESSAGE", "true")]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.reactions().len(), 1);
+    }
+
+    /// A forum only staff can post in, since `@everyone` can't send messages.
+    fn read_only_mock() -> MockApi {
+        let mock = MockApi::default();
+        mock.add_channel(mock::forum(json!({
+            "permission_overwrites": [{
+                "id": GUILD.to_string(),
+                "type": 0,
+                "allow": "0",
+                "deny": Permissions::SEND_MESSAGES.bits().to_string(),
+            }],
+        })));
+        mock
+    }
+
+    #[tokio::test]
+    async fn read_only_forums_are_reacted_to_by_default() {
+        let mock = read_only_mock();
+        let state = mock::state(&mock, &[]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.reactions().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn read_only_forums_can_count_as_locked() {
+        let mock = read_only_mock();
+        let state = mock::state(
+            &mock,
+            &[
+                ("LOCK_READ_ONLY_FORUMS", "true"),
+                ("LOCKED_FORUM_EMOJI", "🔒"),
+            ],
+        );
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.reactions()[0].2, "🔒".parse().unwrap());
+    }
+
+    fn archived_thread() -> Event {
+        thread_create(json!({
+            "thread_metadata": {
+                "archived": true,
+                "auto_archive_duration": 1440,
+                "archive_timestamp": "2024-01-01T00:00:00+00:00",
+                "locked": false,
+            },
+        }))
+    }
+
+    #[tokio::test]
+    async fn archived_threads_are_skipped() {
+        let mock = forum_mock();
+        let state = mock::state(&mock, &[]);
+        handle_event(&state, archived_thread(), None).await;
+        assert!(mock.reactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn archived_threads_get_locked_emoji() {
+        let mock = forum_mock();
+        let state = mock::state(
+            &mock,
+            &[("LOCKED_FORUM_EMOJI", "🔒"), ("KEYWORD_EMOJI", "thread:🧵")],
+        );
+        handle_event(&state, archived_thread(), None).await;
+        // Keyword rules don't apply to locked threads either
+        assert_eq!(
+            mock.reactions(),
+            vec![(Id::new(THREAD), Id::new(THREAD), "🔒".parse().unwrap())]
+        );
+    }
+
+    const STAFF_ROLE: u64 = 10;
+
+    fn role_mock(roles: &[u64]) -> MockApi {
+        let mock = forum_mock();
+        mock.add_member(Id::new(GUILD), mock::member(AUTHOR, roles));
+        mock
+    }
+
+    fn role_state(mock: &MockApi) -> AppState {
+        mock::state(mock, &[("ROLE_EMOJI", "10:⭐")])
+    }
+
+    #[tokio::test]
+    async fn author_role_picks_emoji() {
+        let mock = role_mock(&[STAFF_ROLE + 1, STAFF_ROLE]);
+        let state = role_state(&mock);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(
+            mock.calls()[1],
+            Call::GuildMember(Id::new(GUILD), Id::new(AUTHOR))
+        );
+        assert_eq!(mock.reactions()[0].2, "⭐".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn author_without_role_gets_default_emoji() {
+        let mock = role_mock(&[STAFF_ROLE + 1]);
+        let state = role_state(&mock);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.reactions()[0].2, "⬆️".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn author_who_left_gets_default_emoji() {
+        let mock = forum_mock();
+        let state = role_state(&mock);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.count("guild_member"), 1);
+        assert_eq!(mock.reactions()[0].2, "⬆️".parse().unwrap());
+        assert_eq!(state.metrics.errors_total(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn member_roles_are_reused_until_ttl() {
+        let mock = role_mock(&[STAFF_ROLE]);
+        let state = mock::state(
+            &mock,
+            &[("ROLE_EMOJI", "10:⭐"), ("MEMBER_CACHE_SECS", "60")],
+        );
+        handle_event(&state, thread_create(json!({})), None).await;
+        tokio::time::advance(Duration::from_secs(59)).await;
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.count("guild_member"), 1);
+        tokio::time::advance(Duration::from_secs(2)).await;
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.count("guild_member"), 2);
+        assert_eq!(mock.reactions().len(), 3);
+    }
+
+    fn crosspost_mock() -> MockApi {
+        let mock = forum_mock();
+        mock.add_message(mock::message(json!({
+            "flags": MessageFlags::IS_CROSSPOST.bits(),
+        })));
+        mock
+    }
+
+    #[tokio::test]
+    async fn crossposts_can_be_skipped() {
+        let mock = crosspost_mock();
+        let state = mock::state(&mock, &[("SKIP_CROSSPOSTS", "true")]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.count("message"), 1);
+        assert!(mock.reactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn crossposts_get_their_own_emoji() {
+        let mock = crosspost_mock();
+        let state = mock::state(&mock, &[("CROSSPOST_EMOJI", "📣")]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.reactions()[0].2, "📣".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn other_messages_get_default_emoji() {
+        let mock = forum_mock();
+        mock.add_message(mock::message(json!({})));
+        let state = mock::state(
+            &mock,
+            &[("CROSSPOST_EMOJI", "📣"), ("SKIP_CROSSPOSTS", "false")],
+        );
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.reactions()[0].2, "⬆️".parse().unwrap());
+    }
+
+    fn webhook_mock() -> MockApi {
+        let mock = forum_mock();
+        mock.add_message(mock::message(json!({"webhook_id": "50"})));
+        mock
+    }
+
+    #[tokio::test]
+    async fn webhook_threads_can_be_skipped() {
+        let mock = webhook_mock();
+        let state = mock::state(&mock, &[("SKIP_WEBHOOK_THREADS", "true")]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.count("message"), 1);
+        assert!(mock.reactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn webhook_threads_are_reacted_to_by_default() {
+        let mock = webhook_mock();
+        let state = mock::state(&mock, &[]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        // Without the flag, nothing needs the starter message
+        assert_eq!(mock.count("message"), 0);
+        assert_eq!(mock.reactions().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn user_threads_are_reacted_to_when_skipping_webhooks() {
+        let mock = forum_mock();
+        mock.add_message(mock::message(json!({})));
+        let state = mock::state(&mock, &[("SKIP_WEBHOOK_THREADS", "true")]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.reactions().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn forum_default_reaction_replaces_configured_emoji() {
+        let mock = MockApi::default();
+        mock.add_channel(mock::forum(json!({
+            "default_reaction_emoji": {"emoji_id": null, "emoji_name": "👍"},
+        })));
+        let state = mock::state(&mock, &[("REACTION_EMOJI", "⬆️")]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.reactions()[0].2, "👍".parse().unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spaced_edit_reactions_do_not_block_the_event_loop() {
+        let mock = forum_mock();
+        let state = mock::state(
+            &mock,
+            &[
+                ("KEYWORDS_ON_EDIT", "true"),
+                ("KEYWORD_EMOJI", "bug:🐛,crash:💥"),
+                ("REACTION_INTERVAL_MS", "1000"),
+            ],
+        );
+        handle_event(&state, thread_create(json!({})), None).await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let start = tokio::time::Instant::now();
+        handle_event(&state, thread_update(json!({"name": "bug crash"})), None).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(mock.reactions().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_do_not_block_the_event_loop() {
+        let mock = forum_mock();
+        let state = mock::state(&mock, &[("RETRY_BUDGET", "10")]);
+        mock.fail("create_reaction", 500);
+        mock.fail("create_reaction", 500);
+        let start = tokio::time::Instant::now();
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        // Backing off takes 1s, then 2s
+        tokio::time::sleep(Duration::from_secs(4)).await;
+        assert_eq!(mock.count("create_reaction"), 3);
+    }
+
+    fn keyword_state(mock: &MockApi, max_reactions: &str) -> AppState {
+        mock::state(
+            mock,
+            &[
+                ("KEYWORDS_ON_EDIT", "true"),
+                ("KEYWORD_EMOJI", "bug:🐛,crash:💥,docs:📖"),
+                ("MAX_REACTIONS", max_reactions),
+            ],
+        )
+    }
+
+    fn reacted(mock: &MockApi) -> Vec<Emoji> {
+        mock.reactions()
+            .into_iter()
+            .map(|(_, _, emoji)| emoji)
+            .collect()
+    }
+
+    fn emoji(s: &str) -> Emoji {
+        s.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn edit_adds_new_keyword_emoji() {
+        let mock = forum_mock();
+        let state = keyword_state(&mock, "20");
+        handle_event(&state, thread_create(json!({"name": "a bug"})), None).await;
+        handle_event(&state, thread_update(json!({"name": "a bug crash"})), None).await;
+        assert_eq!(reacted(&mock), [emoji("⬆️"), emoji("🐛"), emoji("💥")]);
+    }
+
+    #[tokio::test]
+    async fn edit_does_not_repeat_emoji() {
+        let mock = forum_mock();
+        let state = keyword_state(&mock, "20");
+        handle_event(&state, thread_create(json!({"name": "a bug"})), None).await;
+        handle_event(&state, thread_update(json!({"name": "a bug crash"})), None).await;
+        handle_event(&state, thread_update(json!({"name": "a crash bug"})), None).await;
+        handle_event(&state, thread_update(json!({"name": "just words"})), None).await;
+        assert_eq!(reacted(&mock), [emoji("⬆️"), emoji("🐛"), emoji("💥")]);
+    }
+
+    #[tokio::test]
+    async fn edit_respects_max_reactions() {
+        let mock = forum_mock();
+        let state = keyword_state(&mock, "2");
+        handle_event(&state, thread_create(json!({"name": "nothing"})), None).await;
+        handle_event(
+            &state,
+            thread_update(json!({"name": "bug crash docs"})),
+            None,
+        )
+        .await;
+        // Only room for one more next to the default emoji
+        assert_eq!(reacted(&mock), [emoji("⬆️"), emoji("🐛")]);
+        handle_event(&state, thread_update(json!({"name": "docs"})), None).await;
+        assert_eq!(mock.reactions().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn edits_of_untracked_threads_are_ignored() {
+        let mock = forum_mock();
+        let state = keyword_state(&mock, "20");
+        handle_event(&state, thread_update(json!({"name": "a bug"})), None).await;
+        assert!(mock.calls().is_empty());
+    }
+
+    fn snowflake_at(time: SystemTime) -> Id<ChannelMarker> {
+        let millis = time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        Id::new((millis - DISCORD_EPOCH_MILLIS) << 22)
+    }
+
+    #[test]
+    fn lag_is_read_from_the_thread_id() {
+        let id = snowflake_at(SystemTime::now() - Duration::from_secs(60));
+        let lag = creation_lag(id);
+        assert!(lag >= Duration::from_secs(60) && lag < Duration::from_secs(61));
+    }
+
+    #[test]
+    fn threads_from_the_future_have_no_lag() {
+        let id = snowflake_at(SystemTime::now() + Duration::from_secs(60));
+        assert_eq!(creation_lag(id), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn lag_is_only_measured_for_new_threads() {
+        let mock = forum_mock();
+        let state = mock::state(&mock, &[]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert!(state.metrics.render().contains("xlr_event_lag_seconds 0\n"));
+        handle_event(&state, thread_create(json!({"newly_created": true})), None).await;
+        assert!(!state.metrics.render().contains("xlr_event_lag_seconds 0\n"));
+    }
+
+    #[tokio::test]
+    async fn successful_reads_do_not_reset_safe_mode() {
+        let mock = forum_mock();
+        mock.add_message(mock::message(json!({})));
+        let state = mock::state(
+            &mock,
+            &[
+                ("SAFE_MODE_THRESHOLD", "2"),
+                ("RETRY_BUDGET", "0"),
+                ("REQUIRE_STARTER_MESSAGE", "true"),
+            ],
+        );
+        for _ in 0..2 {
+            mock.fail("create_reaction", 500);
+            handle_event(&state, thread_create(json!({})), None).await;
+        }
+        // Both handlers fetched the starter message before their reaction failed
+        assert_eq!(mock.count("message"), 2);
+        assert!(!state.safe_mode.allow());
+    }
+
+    #[tokio::test]
+    async fn successful_threads_reset_safe_mode() {
+        let mock = forum_mock();
+        let state = mock::state(
+            &mock,
+            &[("SAFE_MODE_THRESHOLD", "2"), ("RETRY_BUDGET", "0")],
+        );
+        mock.fail("create_reaction", 500);
+        handle_event(&state, thread_create(json!({})), None).await;
+        handle_event(&state, thread_create(json!({})), None).await;
+        mock.fail("create_reaction", 500);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert!(state.safe_mode.allow());
+    }
+
+    #[tokio::test]
+    async fn every_cache_is_measured() {
+        let mock = forum_mock();
+        let state = mock::state(&mock, &[("KEYWORDS_ON_EDIT", "true")]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        state.metrics.cache_sizes(&state.cache_sizes());
+        let rendered = state.metrics.render();
+        assert!(rendered.contains("xlr_cache_entries{cache=\"forums\"} 1\n"));
+        assert!(rendered.contains("xlr_cache_entries{cache=\"applied\"} 1\n"));
+        assert!(rendered.contains("xlr_cache_entries{cache=\"no_access\"} 0\n"));
+        assert!(rendered.contains("xlr_cache_entries{cache=\"member_roles\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn leaving_a_guild_purges_its_caches() {
+        let mock = role_mock(&[STAFF_ROLE]);
+        let state = mock::state(
+            &mock,
+            &[("ROLE_EMOJI", "10:⭐"), ("KEYWORDS_ON_EDIT", "true")],
+        );
+        handle_event(&state, thread_create(json!({})), None).await;
+        let denied_at = Instant::now();
+        {
+            let mut no_access = state.no_access.write();
+            no_access.insert(Id::new(20), (Some(Id::new(GUILD)), denied_at));
+            no_access.insert(Id::new(21), (Some(Id::new(5)), denied_at));
+        }
+        let guild_delete = GuildDelete {
+            id: Id::new(GUILD),
+            unavailable: false,
+        };
+        handle_event(&state, Event::GuildDelete(guild_delete), None).await;
+        assert_eq!(
+            state.cache_sizes(),
+            [
+                ("forums", 0),
+                ("no_access", 1),
+                ("applied", 0),
+                ("member_roles", 0)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn deleted_thread_is_skipped() {
+        let mock = forum_mock();
+        mock.fail("create_reaction", 404);
+        let state = mock::state(
+            &mock,
+            &[("REACTION_EMOJI", "⬆️,⬇️"), ("KEYWORDS_ON_EDIT", "true")],
+        );
+        handle_event(&state, thread_create(json!({})), None).await;
+        // The first failure stops the rest of the reactions
+        assert_eq!(mock.reactions().len(), 1);
+        assert_eq!(mock.reactions()[0].0, Id::new(THREAD));
+        assert_eq!(state.applied.read().len(), 0);
+        assert_eq!(state.no_access.read().len(), 0);
+        assert_eq!(state.metrics.errors_total(), 0);
+    }
+
+    /// A mock whose starter message already has `emoji` on it from the bot.
+    fn reacted_mock(emoji: &str) -> MockApi {
+        let mock = forum_mock();
+        mock.add_message(mock::message(json!({
+            "reactions": [{
+                "count": 1,
+                "count_details": {"burst": 0, "normal": 1},
+                "burst_colors": [],
+                "me": true,
+                "me_burst": false,
+                "emoji": {"id": null, "name": emoji},
+            }],
+        })));
+        mock
+    }
+
+    fn skip_existing_state(mock: &MockApi) -> AppState {
+        mock::state(
+            mock,
+            &[
+                ("SKIP_EXISTING_REACTIONS", "true"),
+                ("WELCOME_MESSAGE", "Hi {author}"),
+                ("AUTO_ARCHIVE_MINUTES", "60"),
+            ],
+        )
+    }
+
+    #[tokio::test]
+    async fn threads_with_every_reaction_are_left_alone() {
+        let mock = reacted_mock("⬆️");
+        let state = skip_existing_state(&mock);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert!(mock.reactions().is_empty());
+        assert_eq!(mock.count("create_message"), 0);
+        // The archive duration is still the forum's to decide
+        assert_eq!(mock.count("update_thread"), 1);
+    }
+
+    #[tokio::test]
+    async fn threads_missing_a_reaction_are_still_welcomed() {
+        let mock = reacted_mock("⭐");
+        let state = skip_existing_state(&mock);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.reactions().len(), 1);
+        assert_eq!(mock.count("create_message"), 1);
+    }
+
+    #[tokio::test]
+    async fn long_welcome_messages_are_cut_short() {
+        let mock = forum_mock();
+        let welcome = format!("{}{{thread}}", "a".repeat(1990));
+        let state = mock::state(&mock, &[("WELCOME_MESSAGE", &welcome)]);
+        handle_event(&state, thread_create(json!({"name": "a long title"})), None).await;
+        let Some(Call::CreateMessage(_, content)) = mock.calls().pop() else {
+            panic!("no welcome message was posted");
+        };
+        assert_eq!(content.chars().count(), config::MAX_MESSAGE_LENGTH);
+        assert!(content.ends_with("aa long ti…"));
+    }
+
+    #[tokio::test]
+    async fn forbidden_welcome_message_is_not_an_error() {
+        let mock = forum_mock();
+        mock.fail("create_message", 403);
+        let state = mock::state(&mock, &[("WELCOME_MESSAGE", "Hi {author}")]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(
+            mock.calls().last(),
+            Some(&Call::CreateMessage(Id::new(THREAD), "Hi <@4>".to_owned()))
+        );
+        assert_eq!(state.metrics.errors_total(), 0);
+    }
+
+    #[tokio::test]
+    async fn forbidden_auto_archive_is_not_an_error() {
+        let mock = forum_mock();
+        mock.fail("update_thread", 403);
+        let state = mock::state(&mock, &[("AUTO_ARCHIVE_MINUTES", "10080")]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(mock.count("update_thread"), 1);
+        assert_eq!(state.metrics.errors_total(), 0);
+    }
+
+    #[tokio::test]
+    async fn auto_archive_is_set_without_reactions() {
+        let mock = forum_mock();
+        mock.fail("create_reaction", 403);
+        let state = mock::state(&mock, &[("AUTO_ARCHIVE_MINUTES", "10080")]);
+        handle_event(&state, thread_create(json!({})), None).await;
+        assert_eq!(
+            mock.calls().last(),
+            Some(&Call::SetAutoArchiveDuration(
+                Id::new(THREAD),
+                AutoArchiveDuration::Week
+            ))
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::{path::PathBuf, str::FromStr};
+
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::config::Config;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+impl LogRotation {
+    const fn rotation(self) -> Rotation {
+        match self {
+            Self::Minutely => Rotation::MINUTELY,
+            Self::Hourly => Rotation::HOURLY,
+            Self::Daily => Rotation::DAILY,
+            Self::Never => Rotation::NEVER,
+        }
+    }
+}
+
+impl FromStr for LogRotation {
+    type Err = LogRotationParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "minutely" => Ok(Self::Minutely),
+            "hourly" => Ok(Self::Hourly),
+            "daily" => Ok(Self::Daily),
+            "never" => Ok(Self::Never),
+            _ => Err(LogRotationParseError(s.to_owned())),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown log rotation `{0}`, expected minutely, hourly, daily or never")]
+pub struct LogRotationParseError(String);
+
+#[derive(thiserror::Error, Debug)]
+#[error("LOG_DIR `{}` can't be used: {source}", dir.display())]
+pub struct LogDirError {
+    dir: PathBuf,
+    source: Box<dyn std::error::Error + Send + Sync>,
+}
+
+/// Install the global tracing subscriber, creating the log directory if needed.
+///
+/// The returned guard flushes the log file when dropped, so it must be held until shutdown.
+pub fn init(config: &Config) -> Result<Option<WorkerGuard>, LogDirError> {
+    let stdout = config.log_stdout.then(tracing_subscriber::fmt::layer);
+    let (file, guard) = match &config.log_dir {
+        Some(dir) => {
+            let error = |source: Box<dyn std::error::Error + Send + Sync>| LogDirError {
+                dir: dir.clone(),
+                source,
+            };
+            std::fs::create_dir_all(dir).map_err(|source| error(source.into()))?;
+            let mut builder = RollingFileAppender::builder()
+                .rotation(config.log_rotation.rotation())
+                .filename_prefix("xlr")
+                .filename_suffix("log");
+            if let Some(max) = config.log_max_files {
+                builder = builder.max_log_files(max);
+            }
+            let appender = builder.build(dir).map_err(|source| error(source.into()))?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+    tracing_subscriber::registry()
+        .with(stdout)
+        .with(file)
+        .with(EnvFilter::from_default_env())
+        .init();
+    Ok(guard)
+}
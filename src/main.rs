@@ -1,15 +1,11 @@
 use std::sync::Arc;
 
-use ahash::AHashMap;
-use parking_lot::RwLock;
-use tokio::sync::oneshot::Receiver;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use twilight_gateway::Shard;
-use twilight_http::{request::channel::reaction::RequestReactionType, Client as DiscordClient};
-use twilight_model::{
-    channel::ChannelType,
-    gateway::{event::Event, payload::incoming::ThreadCreate, CloseFrame, Intents, ShardId},
-    id::{marker::ChannelMarker, Id},
+use twilight_gateway::{Config as ShardConfig, Shard};
+use twilight_http::Client as DiscordClient;
+use twilight_model::gateway::ShardId;
+use xlr::{
+    logging, metrics, publish::WebhookPublisher, queue::HttpQueue, removal, rescan, session,
+    Config, InnerAppState,
 };
 
 #[macro_use]
@@ -18,18 +14,61 @@ extern crate tracing;
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
-    let token =
-        std::env::var("DISCORD_TOKEN").expect("Failed to get DISCORD_TOKEN environment variable");
-    let intents = Intents::GUILDS;
-    let shard = Shard::new(ShardId::ONE, token.clone(), intents);
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(problems) => {
+            eprintln!("{problems}");
+            std::process::exit(1);
+        }
+    };
+    let _log_guard = match logging::init(&config) {
+        Ok(guard) => guard,
+        Err(source) => {
+            eprintln!("{source}");
+            std::process::exit(1);
+        }
+    };
+    let intents = config.intents();
+    let mut shard_config = ShardConfig::builder(config.token.as_str().to_owned(), intents);
+    if let Some(session) = config.session_file.as_deref().and_then(session::load) {
+        info!(id = session.id(), "resuming saved gateway session");
+        shard_config = shard_config.session(session);
+    }
+    if let Some(url) = &config.gateway_queue_url {
+        info!(
+            host = url.host(),
+            "identifying through external gateway queue"
+        );
+        shard_config = shard_config.queue(Arc::new(HttpQueue::new(url.as_uri().clone())));
+    }
+    let shard = Shard::with_config(ShardId::ONE, shard_config.build());
     info!("created shard");
-    let client = DiscordClient::new(token);
-    let forums = RwLock::new(AHashMap::with_capacity(256));
-    let state = Arc::new(InnerAppState { client, forums });
+    info!("effective configuration:\n{}", config.summary());
+    let mut client = DiscordClient::builder().token(config.token.as_str().to_owned());
+    if let Some(api_url) = &config.api_url {
+        info!(host = api_url.host, "sending API requests to custom URL");
+        client = client.proxy(api_url.host.clone(), api_url.use_http);
+    }
+    let client = client.build();
+    let event_webhook_url = config.event_webhook_url.clone();
+    let mut state = InnerAppState::new(client, config);
+    if let Some(url) = event_webhook_url {
+        info!(host = url.host(), "publishing thread events");
+        state = state.with_publisher(WebhookPublisher::new(url.as_uri().clone()));
+    }
+    let state = Arc::new(state);
+    if let Some(addr) = state.config().metrics_addr {
+        tokio::spawn(metrics::serve(state.clone(), addr));
+    }
+    if let Some(period) = state.config().cache_stats_interval {
+        tokio::spawn(metrics::log_cache_sizes(state.clone(), period));
+    }
+    if let Some(period) = state.config().http_stats_interval {
+        tokio::spawn(metrics::log_http_stats(state.clone(), period));
+    }
+    if state.removes_reactions() {
+        tokio::spawn(removal::run(state.clone()));
+    }
     let (shutdown_s, shutdown_r) = tokio::sync::oneshot::channel();
     debug!("registering shutdown handler");
     #[cfg(not(unix))]
@@ -47,82 +86,27 @@ async fn main() {
             .send(())
             .expect("Failed to shut down, is the shutdown handler running?");
     });
-    event_loop(&state, shard, shutdown_r).await;
-}
-
-async fn event_loop(state: &AppState, mut shard: Shard, mut shutdown_r: Receiver<()>) {
-    loop {
-        #[allow(clippy::redundant_pub_crate)]
-        let next = tokio::select! {
-            v = shard.next_event() => v,
-            _ = &mut shutdown_r => break,
-        };
-        trace!(?next, "got new event");
-        let event = match next {
-            Ok(event) => event,
-            Err(source) => {
-                error!(?source, "error receiving event");
-                if source.is_fatal() {
-                    break;
-                }
-                continue;
-            }
-        };
-        if let Event::ThreadCreate(thread) = event {
-            wrap_result(on_thread_create(state, thread).await)
-        }
+    if state.config().preseed_cache {
+        let preseed_state = state.clone();
+        tokio::spawn(async move { rescan::preseed(&preseed_state).await });
     }
-    let _ = shard.close(CloseFrame::NORMAL).await;
-}
-
-fn wrap_result<T>(result: Result<T, Error>) {
-    if let Err(source) = result {
-        error!(?source, "encountered an error");
-    }
-}
-
-async fn on_thread_create(state: &AppState, thread: Box<ThreadCreate>) -> Result<(), Error> {
-    let parent = thread.parent_id.ok_or(Error::NoThreadParentId)?;
-    if !is_forum_post(state, parent).await? {
-        debug!(
-            parent = parent.get(),
-            thread = thread.id.get(),
-            "Skipping channel because parent was not a forum"
-        );
-        return Ok(());
-    }
-    state
-        .client
-        .create_reaction(
-            thread.id,
-            thread.id.cast(),
-            &RequestReactionType::Unicode { name: "⬆️" },
-        )
-        .await?;
-    Ok(())
-}
-
-async fn is_forum_post(state: &AppState, parent: Id<ChannelMarker>) -> Result<bool, Error> {
-    if let Some(kind) = state.forums.read().get(&parent) {
-        return Ok(*kind);
-    }
-    let channel_kind = state.client.channel(parent).await?.model().await?.kind;
-    Ok(matches!(channel_kind, ChannelType::GuildForum))
-}
-
-#[derive(thiserror::Error, Debug)]
-pub enum Error {
-    #[error("twilight-http error: {0}")]
-    DiscordApi(#[from] twilight_http::Error),
-    #[error("twilight-http deserializer error: {0}")]
-    BodyDeserialize(#[from] twilight_http::response::DeserializeBodyError),
-    #[error("Discord did not send a parent channel ID, are you sure this is a thread?")]
-    NoThreadParentId,
-}
-
-pub struct InnerAppState {
-    client: DiscordClient,
-    forums: RwLock<AHashMap<Id<ChannelMarker>, bool>>,
+    let rescan_state = state.clone();
+    tokio::spawn(async move {
+        let mut sig =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1()).unwrap();
+        while sig.recv().await.is_some() {
+            info!("Got SIGUSR1, rescanning forums");
+            rescan::rescan(&rescan_state, rescan_state.config().rescan_reseed).await;
+        }
+    });
+    let maintenance_state = state.clone();
+    tokio::spawn(async move {
+        let mut sig =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined2()).unwrap();
+        while sig.recv().await.is_some() {
+            info!("Got SIGUSR2, toggling maintenance mode");
+            maintenance_state.toggle_maintenance();
+        }
+    });
+    xlr::event_loop(&state, shard, shutdown_r).await;
 }
-
-pub type AppState = Arc<InnerAppState>;
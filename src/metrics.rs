@@ -0,0 +1,436 @@
+use std::{
+    convert::Infallible,
+    fmt::Write,
+    future::Future,
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Response, Server,
+};
+use parking_lot::Mutex;
+use tokio::time::MissedTickBehavior;
+
+use crate::{AppState, Error};
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    forum_cache_size: AtomicUsize,
+    handlers_in_flight: AtomicUsize,
+    /// How long after its creation the latest thread was handled
+    event_lag_millis: AtomicU64,
+    /// Whether the lag is over the warning threshold, so it's only logged once
+    lagging: AtomicBool,
+    handlers_finished: AtomicU64,
+    handler_micros: AtomicU64,
+    reactions_added: AtomicU64,
+    forum_cache_high_watermark: AtomicUsize,
+    /// Entries in every cache by name, as of the last time they were counted
+    cache_sizes: Mutex<Vec<(&'static str, usize)>>,
+    errors: Mutex<AHashMap<(&'static str, Option<u16>), u64>>,
+    http: Mutex<AHashMap<&'static str, HttpStats>>,
+    /// Requests since the last periodic summary, across all routes
+    http_window: Mutex<HttpStats>,
+    started: Started,
+}
+
+/// When the bot started, which is when its metrics were created.
+#[derive(Debug)]
+struct Started(Instant);
+
+impl Default for Started {
+    fn default() -> Self {
+        Self(Instant::now())
+    }
+}
+
+pub struct HandlerGuard<'a> {
+    metrics: &'a Metrics,
+    start: Instant,
+}
+
+impl Drop for HandlerGuard<'_> {
+    fn drop(&mut self) {
+        let micros = u64::try_from(self.start.elapsed().as_micros()).unwrap_or(u64::MAX);
+        self.metrics
+            .handler_micros
+            .fetch_add(micros, Ordering::Relaxed);
+        self.metrics
+            .handlers_finished
+            .fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .handlers_in_flight
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HttpStats {
+    pub requests: u64,
+    /// Time spent on requests, including waiting on the ratelimiter
+    pub total: Duration,
+    pub slowest: Duration,
+    /// Requests Discord answered with 429 Too Many Requests
+    pub ratelimited: u64,
+}
+
+impl HttpStats {
+    fn record(&mut self, elapsed: Duration, ratelimited: bool) {
+        self.requests += 1;
+        self.total += elapsed;
+        self.slowest = self.slowest.max(elapsed);
+        self.ratelimited += u64::from(ratelimited);
+    }
+}
+
+impl Metrics {
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the current size of the forum cache, returning the largest size seen so far.
+    pub fn forum_cache_size(&self, size: usize) -> usize {
+        self.forum_cache_size.store(size, Ordering::Relaxed);
+        let previous = self
+            .forum_cache_high_watermark
+            .fetch_max(size, Ordering::Relaxed);
+        previous.max(size)
+    }
+
+    /// Record the current size of every cache.
+    pub fn cache_sizes(&self, sizes: &[(&'static str, usize)]) {
+        *self.cache_sizes.lock() = sizes.to_vec();
+    }
+
+    /// Track a thread handler until the returned guard is dropped.
+    pub fn start_handler(&self) -> HandlerGuard<'_> {
+        self.handlers_in_flight.fetch_add(1, Ordering::Relaxed);
+        HandlerGuard {
+            metrics: self,
+            start: Instant::now(),
+        }
+    }
+
+    /// Record how long after its creation a thread was handled.
+    ///
+    /// Logs a warning when the lag goes over `warn_after`, which means events
+    /// arrive faster than they are processed.
+    pub fn event_lag(&self, lag: Duration, warn_after: Option<Duration>) {
+        let millis = u64::try_from(lag.as_millis()).unwrap_or(u64::MAX);
+        self.event_lag_millis.store(millis, Ordering::Relaxed);
+        let Some(warn_after) = warn_after else {
+            return;
+        };
+        let lagging = lag > warn_after;
+        // Only warn when crossing the threshold, not for every thread above it
+        if self.lagging.swap(lagging, Ordering::Relaxed) != lagging {
+            if lagging {
+                warn!(
+                    lag_ms = millis,
+                    "event handling is falling behind, consider sharding or reducing delays"
+                );
+            } else {
+                info!(lag_ms = millis, "event handling caught up");
+            }
+        }
+    }
+
+    pub fn reaction_added(&self) {
+        self.reactions_added.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a handler error by kind and, for HTTP errors, response status.
+    pub fn error(&self, kind: &'static str, status: Option<u16>) {
+        *self.errors.lock().entry((kind, status)).or_default() += 1;
+    }
+
+    /// Await a Discord API request, recording how long it took and whether it was ratelimited.
+    ///
+    /// The time includes any wait imposed by twilight's ratelimiter, so slow
+    /// requests are the main sign of ratelimit backpressure.
+    pub async fn timed<T>(
+        &self,
+        route: &'static str,
+        request: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let start = Instant::now();
+        let result = request.await;
+        let elapsed = start.elapsed();
+        let ratelimited = result
+            .as_ref()
+            .is_err_and(|source| source.status() == Some(429));
+        self.http
+            .lock()
+            .entry(route)
+            .or_default()
+            .record(elapsed, ratelimited);
+        self.http_window.lock().record(elapsed, ratelimited);
+        result
+    }
+
+    /// Handler errors of every kind so far.
+    pub fn errors_total(&self) -> u64 {
+        self.errors.lock().values().sum()
+    }
+
+    /// Log totals for the whole run, meant for when the bot shuts down.
+    pub fn log_final_summary(&self) {
+        info!(
+            uptime_secs = self.started.0.elapsed().as_secs(),
+            threads = self.handlers_finished.load(Ordering::Relaxed),
+            reactions = self.reactions_added.load(Ordering::Relaxed),
+            errors = self.errors_total(),
+            "final stats"
+        );
+    }
+
+    /// Take the request stats gathered since this was last called.
+    pub fn take_http_window(&self) -> HttpStats {
+        std::mem::take(&mut *self.http_window.lock())
+    }
+
+    /// Render all counters in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::with_capacity(256);
+        counter(
+            &mut out,
+            "xlr_forum_cache_hits_total",
+            "Forum classification lookups answered from the cache",
+            self.cache_hits.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "xlr_forum_cache_misses_total",
+            "Forum classification lookups that required a channel fetch",
+            self.cache_misses.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "xlr_forum_cache_entries",
+            "Channels currently in the forum cache",
+            self.forum_cache_size.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "xlr_forum_cache_high_watermark",
+            "Most channels ever held in the forum cache at once",
+            self.forum_cache_high_watermark.load(Ordering::Relaxed),
+        );
+        header(
+            &mut out,
+            "xlr_cache_entries",
+            "Entries currently in each cache",
+            "gauge",
+        );
+        for (cache, size) in self.cache_sizes.lock().iter() {
+            let _ = writeln!(out, "xlr_cache_entries{{cache=\"{cache}\"}} {size}");
+        }
+        gauge(
+            &mut out,
+            "xlr_handlers_in_flight",
+            "Thread handlers currently running",
+            self.handlers_in_flight.load(Ordering::Relaxed),
+        );
+        header(
+            &mut out,
+            "xlr_event_lag_seconds",
+            "How long after its creation the latest thread was handled",
+            "gauge",
+        );
+        let _ = writeln!(
+            out,
+            "xlr_event_lag_seconds {}",
+            Duration::from_millis(self.event_lag_millis.load(Ordering::Relaxed)).as_secs_f64()
+        );
+        counter(
+            &mut out,
+            "xlr_handlers_finished_total",
+            "Thread handlers that have finished",
+            self.handlers_finished.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "xlr_reactions_added_total",
+            "Reactions added to threads",
+            self.reactions_added.load(Ordering::Relaxed),
+        );
+        header(
+            &mut out,
+            "xlr_handler_duration_seconds_total",
+            "Time spent in thread handlers",
+            "counter",
+        );
+        let _ = writeln!(
+            out,
+            "xlr_handler_duration_seconds_total {}",
+            Duration::from_micros(self.handler_micros.load(Ordering::Relaxed)).as_secs_f64()
+        );
+        header(
+            &mut out,
+            "xlr_errors_total",
+            "Handler errors by kind and HTTP status",
+            "counter",
+        );
+        let mut errors: Vec<_> = self
+            .errors
+            .lock()
+            .iter()
+            .map(|(key, count)| (*key, *count))
+            .collect();
+        errors.sort_unstable();
+        for ((kind, status), count) in errors {
+            let status = status.map_or_else(|| "none".to_owned(), |s| s.to_string());
+            let _ = writeln!(
+                out,
+                "xlr_errors_total{{kind=\"{kind}\",status=\"{status}\"}} {count}"
+            );
+        }
+        let mut http: Vec<_> = self
+            .http
+            .lock()
+            .iter()
+            .map(|(route, stats)| (*route, *stats))
+            .collect();
+        http.sort_unstable_by_key(|(route, _)| *route);
+        header(
+            &mut out,
+            "xlr_http_requests_total",
+            "Discord API requests by route",
+            "counter",
+        );
+        for (route, stats) in &http {
+            let _ = writeln!(
+                out,
+                "xlr_http_requests_total{{route=\"{route}\"}} {}",
+                stats.requests
+            );
+        }
+        header(
+            &mut out,
+            "xlr_http_request_duration_seconds_total",
+            "Time spent on Discord API requests by route, including ratelimiter waits",
+            "counter",
+        );
+        for (route, stats) in &http {
+            let _ = writeln!(
+                out,
+                "xlr_http_request_duration_seconds_total{{route=\"{route}\"}} {}",
+                stats.total.as_secs_f64()
+            );
+        }
+        header(
+            &mut out,
+            "xlr_http_ratelimited_total",
+            "Discord API requests answered with 429 Too Many Requests by route",
+            "counter",
+        );
+        for (route, stats) in &http {
+            let _ = writeln!(
+                out,
+                "xlr_http_ratelimited_total{{route=\"{route}\"}} {}",
+                stats.ratelimited
+            );
+        }
+        out
+    }
+}
+
+// Writing to a String cannot fail, so the results below are ignored
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: usize) {
+    header(out, name, help, "gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+/// Log the size of every cache on an interval, along with the largest size seen.
+pub async fn log_cache_sizes(state: AppState, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let sizes = state.cache_sizes();
+        let [(_, forums), (_, no_access), (_, applied), (_, member_roles)] = sizes;
+        let forums_high_watermark = state.metrics.forum_cache_size(forums);
+        state.metrics.cache_sizes(&sizes);
+        info!(
+            forums,
+            forums_high_watermark, no_access, applied, member_roles, "cache sizes"
+        );
+    }
+}
+
+/// Log a summary of Discord API requests on an interval.
+pub async fn log_http_stats(state: AppState, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes immediately, and there's nothing to report yet
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let stats = state.metrics.take_http_window();
+        if stats.requests == 0 {
+            continue;
+        }
+        let average_ms = stats.total.as_millis() / u128::from(stats.requests);
+        let slowest_ms = stats.slowest.as_millis();
+        if stats.ratelimited > 0 {
+            warn!(
+                requests = stats.requests,
+                average_ms,
+                slowest_ms,
+                ratelimited = stats.ratelimited,
+                "Discord API requests were ratelimited"
+            );
+        } else {
+            info!(
+                requests = stats.requests,
+                average_ms, slowest_ms, "Discord API request summary"
+            );
+        }
+    }
+}
+
+pub async fn serve(state: AppState, addr: SocketAddr) {
+    let make_svc = make_service_fn(move |_conn| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_req| {
+                let sizes = state.cache_sizes();
+                state.metrics.forum_cache_size(sizes[0].1);
+                state.metrics.cache_sizes(&sizes);
+                let body = state.metrics.render();
+                async move { Ok::<_, Infallible>(Response::new(Body::from(body))) }
+            }))
+        }
+    });
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_svc),
+        Err(source) => {
+            error!(?source, %addr, "failed to bind metrics server");
+            return;
+        }
+    };
+    info!(%addr, "serving metrics");
+    if let Err(source) = server.await {
+        error!(?source, "metrics server failed");
+    }
+}
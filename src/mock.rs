@@ -0,0 +1,448 @@
+//! A scripted [`DiscordApi`] for tests, along with fixtures for the models it returns.
+//!
+//! The mock answers from what it has been given, with 404 for anything it
+//! doesn't know about, and records every call. Error statuses can be queued
+//! per route to simulate failures.
+
+// Not every test uses every helper, and the size of `Error` doesn't matter here
+#![allow(dead_code, clippy::result_large_err)]
+
+use std::{collections::VecDeque, sync::Arc};
+
+use ahash::AHashMap;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use twilight_model::{
+    channel::{thread::AutoArchiveDuration, Channel, Message},
+    guild::{Member, Permissions},
+    http::interaction::InteractionResponse,
+    id::{
+        marker::{
+            ApplicationMarker, ChannelMarker, GuildMarker, InteractionMarker, MessageMarker,
+            UserMarker,
+        },
+        Id,
+    },
+    user::CurrentUserGuild,
+};
+
+use crate::{reactions::Emoji, AppState, Config, DiscordApi, Error, InnerAppState};
+
+pub const GUILD: u64 = 1;
+pub const FORUM: u64 = 2;
+pub const THREAD: u64 = 3;
+pub const AUTHOR: u64 = 4;
+
+/// A request the mock received.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Call {
+    Channel(Id<ChannelMarker>),
+    Message(Id<ChannelMarker>, Id<MessageMarker>),
+    LatestMessage(Id<ChannelMarker>),
+    CreateMessage(Id<ChannelMarker>, String),
+    TriggerTyping(Id<ChannelMarker>),
+    CreateReaction(Id<ChannelMarker>, Id<MessageMarker>, Emoji),
+    DeleteOwnReaction(Id<ChannelMarker>, Id<MessageMarker>, Emoji),
+    SetAutoArchiveDuration(Id<ChannelMarker>, AutoArchiveDuration),
+    CurrentUserGuilds(Option<Id<GuildMarker>>),
+    GuildChannels(Id<GuildMarker>),
+    GuildMember(Id<GuildMarker>, Id<UserMarker>),
+    CreateGlobalCommand(String),
+    CreateInteractionResponse(Id<InteractionMarker>, Option<String>),
+}
+
+impl Call {
+    /// The route name, matching the labels used in metrics.
+    pub const fn route(&self) -> &'static str {
+        match self {
+            Self::Channel(_) => "channel",
+            Self::Message(..) => "message",
+            Self::LatestMessage(_) => "channel_messages",
+            Self::CreateMessage(..) => "create_message",
+            Self::TriggerTyping(_) => "typing",
+            Self::CreateReaction(..) => "create_reaction",
+            Self::DeleteOwnReaction(..) => "delete_reaction",
+            Self::SetAutoArchiveDuration(..) => "update_thread",
+            Self::CurrentUserGuilds(_) => "current_user_guilds",
+            Self::GuildChannels(_) => "guild_channels",
+            Self::GuildMember(..) => "guild_member",
+            Self::CreateGlobalCommand(_) => "create_global_command",
+            Self::CreateInteractionResponse(..) => "create_interaction_response",
+        }
+    }
+}
+
+/// Cheap to clone, so a test can keep a handle after moving one into the state.
+#[derive(Clone, Default)]
+pub struct MockApi(Arc<Inner>);
+
+type MessageKey = (Id<ChannelMarker>, Id<MessageMarker>);
+type MemberKey = (Id<GuildMarker>, Id<UserMarker>);
+
+#[derive(Default)]
+struct Inner {
+    channels: Mutex<AHashMap<Id<ChannelMarker>, Channel>>,
+    messages: Mutex<AHashMap<MessageKey, Message>>,
+    members: Mutex<AHashMap<MemberKey, Member>>,
+    guilds: Mutex<Vec<(CurrentUserGuild, Vec<Channel>)>>,
+    failures: Mutex<AHashMap<&'static str, VecDeque<u16>>>,
+    calls: Mutex<Vec<Call>>,
+}
+
+impl MockApi {
+    pub fn add_channel(&self, channel: Channel) {
+        self.0.channels.lock().insert(channel.id, channel);
+    }
+
+    pub fn add_message(&self, message: Message) {
+        self.0
+            .messages
+            .lock()
+            .insert((message.channel_id, message.id), message);
+    }
+
+    pub fn add_member(&self, guild_id: Id<GuildMarker>, member: Member) {
+        self.0
+            .members
+            .lock()
+            .insert((guild_id, member.user.id), member);
+    }
+
+    /// Put the bot in a guild with these channels.
+    pub fn add_guild(&self, guild_id: Id<GuildMarker>, channels: Vec<Channel>) {
+        let guild = serde_json::from_value(json!({
+            "id": guild_id.to_string(),
+            "name": "guild",
+            "icon": null,
+            "owner": false,
+            "permissions": "0",
+            "features": [],
+        }))
+        .expect("guild fixture should deserialize");
+        self.0.guilds.lock().push((guild, channels));
+    }
+
+    /// Answer the next call to `route` with an error of this status.
+    /// Queued failures are used up in order before the route succeeds again.
+    pub fn fail(&self, route: &'static str, status: u16) {
+        self.0
+            .failures
+            .lock()
+            .entry(route)
+            .or_default()
+            .push_back(status);
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.0.calls.lock().clone()
+    }
+
+    pub fn count(&self, route: &str) -> usize {
+        self.0
+            .calls
+            .lock()
+            .iter()
+            .filter(|call| call.route() == route)
+            .count()
+    }
+
+    /// Every reaction attempted, in order.
+    pub fn reactions(&self) -> Vec<(Id<ChannelMarker>, Id<MessageMarker>, Emoji)> {
+        self.0
+            .calls
+            .lock()
+            .iter()
+            .filter_map(|call| match call {
+                Call::CreateReaction(channel, message, emoji) => {
+                    Some((*channel, *message, emoji.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn record(&self, call: Call) -> Result<(), Error> {
+        let route = call.route();
+        self.0.calls.lock().push(call);
+        let status = self
+            .0
+            .failures
+            .lock()
+            .get_mut(route)
+            .and_then(VecDeque::pop_front);
+        status.map_or(Ok(()), |status| Err(Error::MockStatus(status)))
+    }
+}
+
+fn not_found<T>(value: Option<T>) -> Result<T, Error> {
+    value.ok_or(Error::MockStatus(404))
+}
+
+#[async_trait]
+impl DiscordApi for MockApi {
+    async fn channel(&self, channel_id: Id<ChannelMarker>) -> Result<Channel, Error> {
+        self.record(Call::Channel(channel_id))?;
+        not_found(self.0.channels.lock().get(&channel_id).cloned())
+    }
+
+    async fn message(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+    ) -> Result<Message, Error> {
+        self.record(Call::Message(channel_id, message_id))?;
+        not_found(
+            self.0
+                .messages
+                .lock()
+                .get(&(channel_id, message_id))
+                .cloned(),
+        )
+    }
+
+    async fn latest_message(
+        &self,
+        channel_id: Id<ChannelMarker>,
+    ) -> Result<Option<Message>, Error> {
+        self.record(Call::LatestMessage(channel_id))?;
+        Ok(self
+            .0
+            .messages
+            .lock()
+            .values()
+            .filter(|message| message.channel_id == channel_id)
+            .max_by_key(|message| message.id)
+            .cloned())
+    }
+
+    async fn create_message(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        content: &str,
+    ) -> Result<Message, Error> {
+        self.record(Call::CreateMessage(channel_id, content.to_owned()))?;
+        Ok(message(json!({
+            "channel_id": channel_id.to_string(),
+            "content": content,
+        })))
+    }
+
+    async fn trigger_typing(&self, channel_id: Id<ChannelMarker>) -> Result<(), Error> {
+        self.record(Call::TriggerTyping(channel_id))
+    }
+
+    async fn create_reaction(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+        emoji: &Emoji,
+    ) -> Result<(), Error> {
+        self.record(Call::CreateReaction(channel_id, message_id, emoji.clone()))
+    }
+
+    async fn delete_own_reaction(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+        emoji: &Emoji,
+    ) -> Result<(), Error> {
+        self.record(Call::DeleteOwnReaction(
+            channel_id,
+            message_id,
+            emoji.clone(),
+        ))
+    }
+
+    async fn set_auto_archive_duration(
+        &self,
+        thread_id: Id<ChannelMarker>,
+        duration: AutoArchiveDuration,
+    ) -> Result<(), Error> {
+        self.record(Call::SetAutoArchiveDuration(thread_id, duration))
+    }
+
+    async fn current_user_guilds(
+        &self,
+        after: Option<Id<GuildMarker>>,
+    ) -> Result<Vec<CurrentUserGuild>, Error> {
+        self.record(Call::CurrentUserGuilds(after))?;
+        Ok(self
+            .0
+            .guilds
+            .lock()
+            .iter()
+            .map(|(guild, _)| guild.clone())
+            .filter(|guild| after.is_none_or(|after| guild.id > after))
+            .collect())
+    }
+
+    async fn guild_channels(&self, guild_id: Id<GuildMarker>) -> Result<Vec<Channel>, Error> {
+        self.record(Call::GuildChannels(guild_id))?;
+        not_found(
+            self.0
+                .guilds
+                .lock()
+                .iter()
+                .find(|(guild, _)| guild.id == guild_id)
+                .map(|(_, channels)| channels.clone()),
+        )
+    }
+
+    async fn guild_member(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<Member, Error> {
+        self.record(Call::GuildMember(guild_id, user_id))?;
+        not_found(self.0.members.lock().get(&(guild_id, user_id)).cloned())
+    }
+
+    async fn create_global_command(
+        &self,
+        _: Id<ApplicationMarker>,
+        name: &str,
+        _: &str,
+        _: Option<Permissions>,
+    ) -> Result<(), Error> {
+        self.record(Call::CreateGlobalCommand(name.to_owned()))
+    }
+
+    async fn create_interaction_response(
+        &self,
+        _: Id<ApplicationMarker>,
+        interaction_id: Id<InteractionMarker>,
+        _: &str,
+        response: &InteractionResponse,
+    ) -> Result<(), Error> {
+        let content = response.data.as_ref().and_then(|data| data.content.clone());
+        self.record(Call::CreateInteractionResponse(interaction_id, content))
+    }
+}
+
+/// Config with `vars` on top of a token.
+///
+/// Retries are off unless `vars` set `RETRY_BUDGET`, so handlers run inline
+/// and tests can check what they did as soon as `handle_event` returns.
+pub fn config(vars: &[(&str, &str)]) -> Config {
+    Config::from_vars(|name| {
+        if name == "DISCORD_TOKEN" {
+            return Some("test".to_owned());
+        }
+        let value = vars.iter().find(|(key, _)| *key == name);
+        match value {
+            Some((_, value)) => Some((*value).to_owned()),
+            None if name == "RETRY_BUDGET" => Some("0".to_owned()),
+            None => None,
+        }
+    })
+    .expect("test config should be valid")
+}
+
+/// State backed by `mock`, configured with `vars` on top of a token.
+pub fn state(mock: &MockApi, vars: &[(&str, &str)]) -> AppState {
+    Arc::new(InnerAppState::new(mock.clone(), config(vars)))
+}
+
+/// Overwrite the keys of `base` with those of `overrides`.
+fn merge(mut base: Value, overrides: Value) -> Value {
+    if let (Some(base), Value::Object(overrides)) = (base.as_object_mut(), overrides) {
+        base.extend(overrides);
+    }
+    base
+}
+
+/// A forum in [`GUILD`] with id [`FORUM`], changed by `overrides`.
+pub fn forum(overrides: Value) -> Channel {
+    let base = json!({
+        "id": FORUM.to_string(),
+        "type": 15,
+        "guild_id": GUILD.to_string(),
+        "name": "forum",
+    });
+    serde_json::from_value(merge(base, overrides)).expect("channel fixture should deserialize")
+}
+
+/// A public thread in [`FORUM`] with id [`THREAD`], started by [`AUTHOR`],
+/// changed by `overrides`.
+pub fn thread(overrides: Value) -> Channel {
+    let base = json!({
+        "id": THREAD.to_string(),
+        "type": 11,
+        "guild_id": GUILD.to_string(),
+        "parent_id": FORUM.to_string(),
+        "owner_id": AUTHOR.to_string(),
+        "name": "thread",
+    });
+    serde_json::from_value(merge(base, overrides)).expect("channel fixture should deserialize")
+}
+
+/// The starter message of [`THREAD`], changed by `overrides`.
+pub fn message(overrides: Value) -> Message {
+    let base = json!({
+        "id": THREAD.to_string(),
+        "channel_id": THREAD.to_string(),
+        "author": user(AUTHOR),
+        "content": "",
+        "attachments": [],
+        "embeds": [],
+        "edited_timestamp": null,
+        "mention_everyone": false,
+        "mention_roles": [],
+        "mentions": [],
+        "pinned": false,
+        "timestamp": "2024-01-01T00:00:00+00:00",
+        "tts": false,
+        "type": 0,
+    });
+    serde_json::from_value(merge(base, overrides)).expect("message fixture should deserialize")
+}
+
+/// A member with these roles.
+pub fn member(user_id: u64, roles: &[u64]) -> Member {
+    serde_json::from_value(json!({
+        "user": user(user_id),
+        "roles": roles.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        "communication_disabled_until": null,
+        "deaf": false,
+        "flags": 0,
+        "joined_at": "2024-01-01T00:00:00+00:00",
+        "mute": false,
+        "nick": null,
+    }))
+    .expect("member fixture should deserialize")
+}
+
+fn user(id: u64) -> Value {
+    json!({
+        "id": id.to_string(),
+        "username": "user",
+        "discriminator": "0",
+        "avatar": null,
+    })
+}
+
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queued_failures_come_first() {
+        let mock = MockApi::default();
+        mock.add_channel(forum(json!({})));
+        mock.fail("channel", 429);
+        mock.fail("channel", 500);
+        let id = Id::new(FORUM);
+        assert_eq!(mock.channel(id).await.unwrap_err().status(), Some(429));
+        assert_eq!(mock.channel(id).await.unwrap_err().status(), Some(500));
+        assert_eq!(mock.channel(id).await.unwrap().id, id);
+        assert_eq!(mock.calls(), vec![Call::Channel(id); 3]);
+    }
+
+    #[tokio::test]
+    async fn unknown_is_not_found() {
+        let mock = MockApi::default();
+        let error = mock.message(Id::new(THREAD), Id::new(THREAD)).await;
+        assert_eq!(error.unwrap_err().status(), Some(404));
+        assert_eq!(mock.count("message"), 1);
+    }
+}
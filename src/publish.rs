@@ -0,0 +1,123 @@
+//! Publishing an event for every thread XLR handles, for analytics and the like.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use hyper::{
+    client::HttpConnector, header::CONTENT_TYPE, Body, Client, Method, Request, StatusCode, Uri,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde_json::{json, Value};
+use twilight_model::id::{
+    marker::{ChannelMarker, GuildMarker},
+    Id,
+};
+
+/// How long to wait on the webhook. Events are published in the background,
+/// but a hung endpoint would otherwise pile up tasks forever.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A thread XLR finished handling.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThreadEvent {
+    pub guild: Option<Id<GuildMarker>>,
+    pub forum: Id<ChannelMarker>,
+    pub thread: Id<ChannelMarker>,
+    /// The emoji reacted with, in order
+    pub emoji: Vec<String>,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl ThreadEvent {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "guild": self.guild.map(|id| id.to_string()),
+            "forum": self.forum.to_string(),
+            "thread": self.thread.to_string(),
+            "emoji": self.emoji,
+            "timestamp": self.timestamp,
+        })
+    }
+}
+
+/// Somewhere to send thread events. Failures are logged and otherwise ignored.
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    async fn publish(&self, event: &ThreadEvent) -> Result<(), PublishError>;
+}
+
+/// Posts each event as JSON to a URL.
+#[derive(Debug)]
+pub struct WebhookPublisher {
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: Uri,
+}
+
+impl WebhookPublisher {
+    pub fn new(url: Uri) -> Self {
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Self {
+            client: Client::builder().build(connector),
+            url,
+        }
+    }
+}
+
+#[async_trait]
+impl Publisher for WebhookPublisher {
+    async fn publish(&self, event: &ThreadEvent) -> Result<(), PublishError> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(event.to_json().to_string()))?;
+        let response = tokio::time::timeout(TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| PublishError::Timeout)??;
+        if !response.status().is_success() {
+            return Err(PublishError::Status(response.status()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PublishError {
+    #[error("failed to build request: {0}")]
+    Request(#[from] hyper::http::Error),
+    #[error("request failed: {0}")]
+    Http(#[from] hyper::Error),
+    #[error("endpoint responded with {0}")]
+    Status(StatusCode),
+    #[error("endpoint didn't respond within {}s", TIMEOUT.as_secs())]
+    Timeout,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn unresponsive_endpoints_time_out() {
+        // Connections are accepted into the backlog, but nothing ever answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let publisher = WebhookPublisher::new(url.parse().unwrap());
+        let event = ThreadEvent {
+            guild: None,
+            forum: Id::new(1),
+            thread: Id::new(2),
+            emoji: Vec::new(),
+            timestamp: 0,
+        };
+        let result = publisher.publish(&event).await;
+        assert!(matches!(result, Err(PublishError::Timeout)));
+    }
+}
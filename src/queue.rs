@@ -0,0 +1,95 @@
+//! Coordinating gateway identifies through an external session-start queue,
+//! such as [twilight's gateway-queue](https://github.com/twilight-rs/gateway-queue),
+//! so several processes sharing a token don't trip Discord's identify limit.
+
+use std::{future::Future, pin::Pin, time::Duration};
+
+use hyper::{client::HttpConnector, Client, Uri};
+use twilight_gateway::queue::Queue;
+
+/// How long to wait before asking the queue again after a failed request.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A [`Queue`] that asks an HTTP service for permission to identify.
+///
+/// The service is sent `GET <url>?shard=<id>`, keeping any query the URL
+/// already has, and is expected to respond
+/// once the shard may start its session.
+#[derive(Debug)]
+pub struct HttpQueue {
+    client: Client<HttpConnector>,
+    url: Uri,
+}
+
+impl HttpQueue {
+    pub fn new(url: Uri) -> Self {
+        Self {
+            client: Client::new(),
+            url,
+        }
+    }
+
+    fn request_uri(&self, shard_id: u64) -> Uri {
+        let separator = if self.url.query().is_some() { '&' } else { '?' };
+        format!("{}{separator}shard={shard_id}", self.url)
+            .parse()
+            .expect("queue URL with a shard query should still be a valid URI")
+    }
+}
+
+impl Queue for HttpQueue {
+    fn request<'a>(
+        &'a self,
+        [id, _total]: [u64; 2],
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(async move {
+            let uri = self.request_uri(id);
+            loop {
+                match self.client.get(uri.clone()).await {
+                    Ok(response) if response.status().is_success() => {
+                        debug!(shard = id, "gateway queue allowed identify");
+                        return;
+                    }
+                    Ok(response) => {
+                        warn!(shard = id, status = %response.status(), "gateway queue refused request, retrying");
+                    }
+                    Err(source) => {
+                        warn!(
+                            ?source,
+                            shard = id,
+                            "failed to reach gateway queue, retrying"
+                        );
+                    }
+                }
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_uri(url: &str) -> String {
+        HttpQueue::new(url.parse().unwrap())
+            .request_uri(3)
+            .to_string()
+    }
+
+    #[test]
+    fn shard_is_added_as_the_query() {
+        assert_eq!(
+            request_uri("http://queue.local/identify"),
+            "http://queue.local/identify?shard=3"
+        );
+    }
+
+    #[test]
+    fn existing_query_is_kept() {
+        assert_eq!(
+            request_uri("http://queue.local/identify?key=abc"),
+            "http://queue.local/identify?key=abc&shard=3"
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use std::{fmt, str::FromStr};
+
+use ahash::AHashSet;
+use twilight_http::request::channel::reaction::RequestReactionType;
+use twilight_model::{
+    channel::message::ReactionType,
+    id::{marker::EmojiMarker, Id},
+};
+
+/// The most reactions Discord allows on a single message.
+pub const DISCORD_MAX_REACTIONS: usize = 20;
+
+/// An owned emoji that can be configured and reacted with.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Emoji {
+    Unicode(String),
+    Custom {
+        id: Id<EmojiMarker>,
+        name: Option<String>,
+    },
+}
+
+impl Emoji {
+    pub fn as_request(&self) -> RequestReactionType<'_> {
+        match self {
+            Self::Unicode(name) => RequestReactionType::Unicode { name },
+            Self::Custom { id, name } => RequestReactionType::Custom {
+                id: *id,
+                name: name.as_deref(),
+            },
+        }
+    }
+
+    /// Whether a reaction already on a message is this emoji.
+    ///
+    /// Variation selectors are ignored, since Discord isn't consistent about them.
+    pub fn matches(&self, reaction: &ReactionType) -> bool {
+        match (self, reaction) {
+            (Self::Unicode(ours), ReactionType::Unicode { name }) => ours
+                .chars()
+                .filter(|c| *c != '\u{FE0F}')
+                .eq(name.chars().filter(|c| *c != '\u{FE0F}')),
+            (Self::Custom { id: ours, .. }, ReactionType::Custom { id, .. }) => ours == id,
+            _ => false,
+        }
+    }
+
+    /// Custom emoji are identified by their id alone, the name is only decorative.
+    fn key(&self) -> EmojiKey<'_> {
+        match self {
+            Self::Unicode(name) => EmojiKey::Unicode(name),
+            Self::Custom { id, .. } => EmojiKey::Custom(*id),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum EmojiKey<'a> {
+    Unicode(&'a str),
+    Custom(Id<EmojiMarker>),
+}
+
+impl FromStr for Emoji {
+    type Err = EmojiParseError;
+
+    /// Accepts a unicode emoji, `name:id`, or Discord's `<:name:id>` / `<a:name:id>` syntax.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(EmojiParseError::Empty);
+        }
+        let inner = s
+            .strip_prefix('<')
+            .and_then(|v| v.strip_suffix('>'))
+            .map(|v| {
+                v.strip_prefix("a:")
+                    .or_else(|| v.strip_prefix(':'))
+                    .unwrap_or(v)
+            });
+        let Some((name, id)) = inner.or(Some(s)).and_then(|v| v.rsplit_once(':')) else {
+            // Every unicode emoji has a non-ASCII character, so this is likely
+            // a name like `thumbsup` or a broken custom emoji
+            if s.is_ascii() {
+                return Err(EmojiParseError::NotEmoji(s.to_owned()));
+            }
+            return Ok(Self::Unicode(normalize_unicode(s)));
+        };
+        let id = id
+            .parse()
+            .map_err(|_| EmojiParseError::InvalidId(id.to_owned()))?;
+        let name = (!name.is_empty()).then(|| name.to_owned());
+        Ok(Self::Custom { id, name })
+    }
+}
+
+impl fmt::Display for Emoji {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unicode(name) => f.write_str(name),
+            Self::Custom { id, name } => write!(f, "{}:{id}", name.as_deref().unwrap_or("")),
+        }
+    }
+}
+
+/// U+FE0F, which asks for a character to be shown as an emoji rather than as text.
+const EMOJI_PRESENTATION_SELECTOR: char = '\u{FE0F}';
+
+/// U+20E3, which turns a digit, `#` or `*` into a keycap emoji.
+const COMBINING_KEYCAP: char = '\u{20E3}';
+
+/// Characters below U+1F000 that are shown as emoji even without a selector.
+/// Every other emoji in that range defaults to its text presentation.
+const BMP_EMOJI_PRESENTATION: &[(char, char)] = &[
+    ('\u{231A}', '\u{231B}'),
+    ('\u{23E9}', '\u{23EC}'),
+    ('\u{23F0}', '\u{23F0}'),
+    ('\u{23F3}', '\u{23F3}'),
+    ('\u{25FD}', '\u{25FE}'),
+    ('\u{2614}', '\u{2615}'),
+    ('\u{2648}', '\u{2653}'),
+    ('\u{267F}', '\u{267F}'),
+    ('\u{2693}', '\u{2693}'),
+    ('\u{26A1}', '\u{26A1}'),
+    ('\u{26AA}', '\u{26AB}'),
+    ('\u{26BD}', '\u{26BE}'),
+    ('\u{26C4}', '\u{26C5}'),
+    ('\u{26CE}', '\u{26CE}'),
+    ('\u{26D4}', '\u{26D4}'),
+    ('\u{26EA}', '\u{26EA}'),
+    ('\u{26F2}', '\u{26F3}'),
+    ('\u{26F5}', '\u{26F5}'),
+    ('\u{26FA}', '\u{26FA}'),
+    ('\u{26FD}', '\u{26FD}'),
+    ('\u{2705}', '\u{2705}'),
+    ('\u{270A}', '\u{270B}'),
+    ('\u{2728}', '\u{2728}'),
+    ('\u{274C}', '\u{274C}'),
+    ('\u{274E}', '\u{274E}'),
+    ('\u{2753}', '\u{2755}'),
+    ('\u{2757}', '\u{2757}'),
+    ('\u{2795}', '\u{2797}'),
+    ('\u{27B0}', '\u{27B0}'),
+    ('\u{27BF}', '\u{27BF}'),
+    ('\u{2B1B}', '\u{2B1C}'),
+    ('\u{2B50}', '\u{2B50}'),
+    ('\u{2B55}', '\u{2B55}'),
+];
+
+/// Add the emoji presentation selector to emoji that would otherwise be
+/// shown as text, like `⬆` (which becomes `⬆️`) and keycaps like `1⃣`.
+///
+/// Only single characters below U+1F000 and keycaps are changed. Almost
+/// everything above that is shown as an emoji already, and multi-character
+/// sequences are assumed to have been written correctly.
+fn normalize_unicode(emoji: &str) -> String {
+    let mut chars = emoji.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(base), None, None) if needs_selector(base) => {
+            format!("{base}{EMOJI_PRESENTATION_SELECTOR}")
+        }
+        (Some(base @ ('0'..='9' | '#' | '*')), Some(COMBINING_KEYCAP), None) => {
+            format!("{base}{EMOJI_PRESENTATION_SELECTOR}{COMBINING_KEYCAP}")
+        }
+        _ => emoji.to_owned(),
+    }
+}
+
+fn needs_selector(c: char) -> bool {
+    !c.is_ascii()
+        && c < '\u{1F000}'
+        && !BMP_EMOJI_PRESENTATION
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&c))
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum EmojiParseError {
+    #[error("emoji must not be empty")]
+    Empty,
+    #[error("invalid custom emoji id `{0}`")]
+    InvalidId(String),
+    #[error("`{0}` is not a unicode emoji, `name:id` or `<:name:id>`")]
+    NotEmoji(String),
+}
+
+/// Which message in a new thread gets reacted to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReactionTarget {
+    /// The thread's starter message, which needs no extra API call.
+    #[default]
+    Starter,
+    /// The most recent message in the thread, fetched when the thread is created.
+    Latest,
+}
+
+impl FromStr for ReactionTarget {
+    type Err = ReactionTargetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "starter" => Ok(Self::Starter),
+            "latest" => Ok(Self::Latest),
+            _ => Err(ReactionTargetParseError(s.to_owned())),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown reaction target `{0}`, expected starter or latest")]
+pub struct ReactionTargetParseError(String);
+
+/// Adds an emoji to threads whose title contains a keyword, ignoring case.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeywordRule {
+    /// Lowercase, so titles only need lowercasing once
+    keyword: String,
+    emoji: Emoji,
+}
+
+impl KeywordRule {
+    /// The emoji of every rule matching `title`, in rule order.
+    pub fn matching(rules: &[Self], title: &str) -> Vec<Emoji> {
+        let title = title.to_lowercase();
+        rules
+            .iter()
+            .filter(|rule| title.contains(&rule.keyword))
+            .map(|rule| rule.emoji.clone())
+            .collect()
+    }
+}
+
+impl FromStr for KeywordRule {
+    type Err = KeywordRuleParseError;
+
+    /// Accepts `keyword:emoji`, where the emoji can be anything [`Emoji`] accepts.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((keyword, emoji)) = s.split_once(':') else {
+            return Err(KeywordRuleParseError::MissingEmoji(s.to_owned()));
+        };
+        let keyword = keyword.trim().to_lowercase();
+        if keyword.is_empty() {
+            return Err(KeywordRuleParseError::EmptyKeyword);
+        }
+        Ok(Self {
+            keyword,
+            emoji: emoji.parse()?,
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum KeywordRuleParseError {
+    #[error("keyword rule `{0}` has no emoji, expected keyword:emoji")]
+    MissingEmoji(String),
+    #[error("keyword must not be empty")]
+    EmptyKeyword,
+    #[error(transparent)]
+    Emoji(#[from] EmojiParseError),
+}
+
+/// Merge the emoji suggested by each rule source into the list to react with.
+///
+/// Sources are consulted in order. Duplicate emoji keep their first position,
+/// and the result is truncated to `max` entries (and never more than Discord
+/// allows), dropping emoji from the end and logging a warning.
+pub fn resolve<'a, I, S>(sources: I, max: usize) -> Vec<&'a Emoji>
+where
+    I: IntoIterator<Item = S>,
+    S: IntoIterator<Item = &'a Emoji>,
+{
+    let max = max.min(DISCORD_MAX_REACTIONS);
+    let mut seen = AHashSet::new();
+    let mut emoji: Vec<&Emoji> = sources
+        .into_iter()
+        .flatten()
+        .filter(|emoji| seen.insert(emoji.key()))
+        .collect();
+    if emoji.len() > max {
+        warn!(
+            produced = emoji.len(),
+            max, "reaction rules produced too many emoji, dropping the last ones"
+        );
+        emoji.truncate(max);
+    }
+    emoji
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, sync::Arc};
+
+    use parking_lot::Mutex;
+
+    use super::*;
+
+    fn emoji(s: &str) -> Emoji {
+        s.parse().unwrap()
+    }
+
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Run `f`, returning its result and everything it logged.
+    fn logged<T>(f: impl FnOnce() -> T) -> (T, String) {
+        let buffer = LogBuffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let result = tracing::subscriber::with_default(subscriber, f);
+        let logs = String::from_utf8(buffer.0.lock().clone()).unwrap();
+        (result, logs)
+    }
+
+    #[test]
+    fn resolve_keeps_first_seen_order() {
+        let defaults = [emoji("⬆️"), emoji("⬇️")];
+        let keywords = [emoji("🐛"), emoji("⬆️"), emoji("⬇️"), emoji("✨")];
+        let resolved = resolve([defaults.iter(), keywords.iter()], DISCORD_MAX_REACTIONS);
+        let expected = [emoji("⬆️"), emoji("⬇️"), emoji("🐛"), emoji("✨")];
+        assert_eq!(resolved, expected.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn resolve_dedupes_custom_emoji_by_id() {
+        let defaults = [emoji("upvote:123")];
+        let keywords = [emoji("<:renamed:123>"), emoji(":123"), emoji("other:456")];
+        let resolved = resolve([defaults.iter(), keywords.iter()], DISCORD_MAX_REACTIONS);
+        assert_eq!(resolved, vec![&defaults[0], &keywords[2]]);
+    }
+
+    #[test]
+    fn resolve_truncates_from_the_end() {
+        let defaults = [emoji("⬆️"), emoji("⬇️")];
+        let keywords = [emoji("🐛"), emoji("⬆️"), emoji("✨"), emoji("📖")];
+        let (resolved, logs) = logged(|| resolve([defaults.iter(), keywords.iter()], 3));
+        let expected = [emoji("⬆️"), emoji("⬇️"), emoji("🐛")];
+        assert_eq!(resolved, expected.iter().collect::<Vec<_>>());
+        assert!(logs.contains("WARN"), "{logs}");
+        assert!(logs.contains("produced=5"), "{logs}");
+    }
+
+    #[test]
+    fn resolve_is_capped_at_discord_limit() {
+        let many: Vec<Emoji> = (1..=25).map(|id| emoji(&format!("e:{id}"))).collect();
+        let (resolved, _) = logged(|| resolve([many.iter()], 50));
+        assert_eq!(
+            resolved,
+            many[..DISCORD_MAX_REACTIONS].iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn resolve_within_max_does_not_warn() {
+        let defaults = [emoji("⬆️"), emoji("⬇️")];
+        let (resolved, logs) = logged(|| resolve([defaults.iter(), defaults.iter()], 2));
+        assert_eq!(resolved.len(), 2);
+        assert!(logs.is_empty(), "{logs}");
+    }
+
+    #[test]
+    fn text_presentation_gets_selector() {
+        assert_eq!(normalize_unicode("⬆"), "⬆\u{FE0F}");
+        assert_eq!(normalize_unicode("❤"), "❤\u{FE0F}");
+        assert_eq!(emoji("⬆"), emoji("⬆\u{FE0F}"));
+    }
+
+    #[test]
+    fn selector_is_not_doubled() {
+        assert_eq!(normalize_unicode("⬆\u{FE0F}"), "⬆\u{FE0F}");
+        assert_eq!(normalize_unicode("1\u{FE0F}\u{20E3}"), "1\u{FE0F}\u{20E3}");
+    }
+
+    #[test]
+    fn keycaps_get_selector() {
+        assert_eq!(normalize_unicode("1\u{20E3}"), "1\u{FE0F}\u{20E3}");
+        assert_eq!(normalize_unicode("#\u{20E3}"), "#\u{FE0F}\u{20E3}");
+    }
+
+    #[test]
+    fn emoji_presentation_is_unchanged() {
+        // In the BMP but shown as emoji by default
+        assert_eq!(normalize_unicode("⌚"), "⌚");
+        assert_eq!(normalize_unicode("✅"), "✅");
+        assert_eq!(normalize_unicode("👍"), "👍");
+        assert_eq!(normalize_unicode("👍🏽"), "👍🏽");
+    }
+}
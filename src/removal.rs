@@ -0,0 +1,283 @@
+//! Taking the bot's own reactions back off after a voting period, so only
+//! members' votes remain.
+//!
+//! Pending removals are saved to a file if one is configured, so they
+//! survive restarts. Changes are written out once per check rather than on
+//! every reaction, so a busy forum doesn't turn into a stream of file writes.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use serde_json::{json, Value};
+use tokio::time::MissedTickBehavior;
+use twilight_model::id::{
+    marker::{ChannelMarker, MessageMarker},
+    Id,
+};
+
+use crate::{reactions::Emoji, AppState, Config};
+
+/// How often to check for reactions that are due to be removed.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct ReactionRemovals {
+    /// Off if unset
+    after: Option<Duration>,
+    path: Option<PathBuf>,
+    pending: Mutex<Vec<Pending>>,
+    /// Whether `pending` changed since it was last saved
+    dirty: AtomicBool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Pending {
+    channel_id: Id<ChannelMarker>,
+    message_id: Id<MessageMarker>,
+    emoji: Emoji,
+    /// Seconds since the Unix epoch, so it means the same thing after a restart
+    remove_at: u64,
+}
+
+impl Pending {
+    fn to_json(&self) -> Value {
+        json!({
+            "channel_id": self.channel_id.get(),
+            "message_id": self.message_id.get(),
+            "emoji": self.emoji.to_string(),
+            "remove_at": self.remove_at,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        fn id<T>(value: &Value, key: &str) -> Option<Id<T>> {
+            value.get(key)?.as_u64().and_then(Id::new_checked)
+        }
+        Some(Self {
+            channel_id: id(value, "channel_id")?,
+            message_id: id(value, "message_id")?,
+            emoji: value.get("emoji")?.as_str()?.parse().ok()?,
+            remove_at: value.get("remove_at")?.as_u64()?,
+        })
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+impl ReactionRemovals {
+    pub fn new(config: &Config) -> Self {
+        let after = config.remove_reactions_after;
+        let path = config.reaction_removal_file.clone();
+        let pending = match (&after, &path) {
+            (Some(_), Some(path)) => load(path),
+            _ => Vec::new(),
+        };
+        Self {
+            after,
+            path,
+            pending: Mutex::new(pending),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub const fn is_enabled(&self) -> bool {
+        self.after.is_some()
+    }
+
+    /// Remember to remove a reaction the bot just added, if removal is on.
+    pub fn schedule(
+        &self,
+        channel_id: Id<ChannelMarker>,
+        message_id: Id<MessageMarker>,
+        emoji: &Emoji,
+    ) {
+        let Some(after) = self.after else {
+            return;
+        };
+        self.pending.lock().push(Pending {
+            channel_id,
+            message_id,
+            emoji: emoji.clone(),
+            remove_at: unix_now() + after.as_secs(),
+        });
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    fn take_due(&self) -> Vec<Pending> {
+        let now = unix_now();
+        let mut pending = self.pending.lock();
+        let (due, rest): (Vec<_>, _) = pending.drain(..).partition(|entry| entry.remove_at <= now);
+        *pending = rest;
+        if !due.is_empty() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        due
+    }
+
+    /// Put back removals that failed, to try again on the next check.
+    fn requeue(&self, failed: Vec<Pending>) {
+        if failed.is_empty() {
+            return;
+        }
+        self.pending.lock().extend(failed);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Save pending removals to the file, if they changed since the last save.
+    ///
+    /// The file is written on the blocking pool, without holding the lock.
+    pub async fn flush(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let data = {
+            let pending = self.pending.lock();
+            Value::Array(pending.iter().map(Pending::to_json).collect()).to_string()
+        };
+        let path = path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            std::fs::write(&path, data).map_err(|source| (source, path))
+        })
+        .await;
+        if let Ok(Err((source, path))) = result {
+            error!(?source, path = %path.display(), "failed to save pending reaction removals");
+            // Try again on the next flush
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+fn load(path: &Path) -> Vec<Pending> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(source) if source.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(source) => {
+            warn!(?source, path = %path.display(), "failed to read pending reaction removals");
+            return Vec::new();
+        }
+    };
+    let entries = match serde_json::from_slice::<Vec<Value>>(&data) {
+        Ok(entries) => entries,
+        Err(source) => {
+            warn!(?source, path = %path.display(), "failed to parse pending reaction removals");
+            return Vec::new();
+        }
+    };
+    let pending: Vec<_> = entries.iter().filter_map(Pending::from_json).collect();
+    if pending.len() < entries.len() {
+        warn!(
+            skipped = entries.len() - pending.len(),
+            "skipped invalid pending reaction removals"
+        );
+    }
+    info!(pending = pending.len(), "loaded pending reaction removals");
+    pending
+}
+
+/// Remove the bot's reactions once they are due, forever.
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let due = state.removals.take_due();
+        if due.is_empty() {
+            state.removals.flush().await;
+            continue;
+        }
+        let mut failed = Vec::new();
+        let mut removed = 0;
+        for entry in due {
+            let request =
+                state
+                    .client
+                    .delete_own_reaction(entry.channel_id, entry.message_id, &entry.emoji);
+            match state.timed("delete_reaction", request).await {
+                Ok(()) => removed += 1,
+                // The message or reaction is already gone, which is just as good
+                Err(source) if source.status() == Some(404) => {}
+                Err(source) => {
+                    warn!(
+                        ?source,
+                        message = entry.message_id.get(),
+                        "failed to remove reaction, will retry"
+                    );
+                    failed.push(entry);
+                }
+            }
+        }
+        debug!(
+            removed,
+            failed = failed.len(),
+            "removed reactions after voting period"
+        );
+        state.removals.requeue(failed);
+        state.removals.flush().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    fn removals(name: &str) -> (ReactionRemovals, PathBuf) {
+        let path = std::env::temp_dir().join(format!("xlr-removals-{name}.json"));
+        let _ = std::fs::remove_file(&path);
+        let config = mock::config(&[
+            ("REMOVE_REACTIONS_AFTER_SECS", "60"),
+            ("REACTION_REMOVAL_FILE", path.to_str().unwrap()),
+        ]);
+        (ReactionRemovals::new(&config), path)
+    }
+
+    fn schedule(removals: &ReactionRemovals) {
+        removals.schedule(Id::new(1), Id::new(2), &"⬆️".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn saves_wait_for_a_flush() {
+        let (removals, path) = removals("flush");
+        schedule(&removals);
+        schedule(&removals);
+        assert!(!path.exists());
+        removals.flush().await;
+        assert_eq!(load(&path).len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unchanged_removals_are_not_saved() {
+        let (removals, path) = removals("unchanged");
+        schedule(&removals);
+        removals.flush().await;
+        std::fs::remove_file(&path).unwrap();
+        removals.flush().await;
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn removals_survive_a_restart() {
+        let (removals, path) = removals("restart");
+        schedule(&removals);
+        removals.flush().await;
+        let config = mock::config(&[
+            ("REMOVE_REACTIONS_AFTER_SECS", "60"),
+            ("REACTION_REMOVAL_FILE", path.to_str().unwrap()),
+        ]);
+        let restarted = ReactionRemovals::new(&config);
+        assert_eq!(*restarted.pending.lock(), *removals.pending.lock());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
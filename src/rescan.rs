@@ -0,0 +1,150 @@
+use twilight_model::id::{marker::GuildMarker, Id};
+
+use crate::{forums::ParentChannel, AppState, Error};
+
+/// Discord's default (and maximum) page size for the current user's guilds.
+const GUILD_PAGE_SIZE: usize = 200;
+
+/// Throw away every cached forum classification, optionally fetching
+/// the channels of every guild the bot is in to fill the cache back up.
+pub async fn rescan(state: &AppState, reseed: bool) {
+    let cleared = {
+        let mut forums = state.forums.write();
+        let cleared = forums.len();
+        forums.clear();
+        cleared
+    };
+    info!(cleared, "cleared forum cache");
+    if !reseed {
+        return;
+    }
+    match seed_all(state).await {
+        Ok(seeded) => info!(seeded, "re-seeded forum cache"),
+        Err(source) => error!(?source, "failed to re-seed forum cache"),
+    }
+}
+
+/// Fill the forum cache at startup.
+///
+/// With a guild allowlist, only those guilds are fetched, which doubles as a
+/// check that the bot can actually see each of them.
+pub async fn preseed(state: &AppState) {
+    if state.config.guild_allowlist.is_empty() {
+        match seed_all(state).await {
+            Ok(seeded) => info!(seeded, "pre-seeded forum cache"),
+            Err(source) => error!(?source, "failed to pre-seed forum cache"),
+        }
+        return;
+    }
+    let mut seeded = 0;
+    for guild_id in &state.config.guild_allowlist {
+        match seed_guild(state, *guild_id).await {
+            Ok(count) => seeded += count,
+            Err(source) => warn!(
+                ?source,
+                guild = guild_id.get(),
+                "failed to fetch channels of allowlisted guild, is the bot in it?"
+            ),
+        }
+    }
+    info!(seeded, "pre-seeded forum cache");
+}
+
+/// Classify every channel in every (allowed) guild the bot is in.
+async fn seed_all(state: &AppState) -> Result<usize, Error> {
+    let mut seeded = 0;
+    let mut after: Option<Id<GuildMarker>> = None;
+    loop {
+        let guilds = crate::retry::retry(state, || {
+            state.timed(
+                "current_user_guilds",
+                state.client.current_user_guilds(after),
+            )
+        })
+        .await?;
+        for guild in &guilds {
+            if !crate::is_guild_allowed(&state.config, Some(guild.id)) {
+                continue;
+            }
+            // One guild failing shouldn't leave the rest of the cache empty
+            match seed_guild(state, guild.id).await {
+                Ok(count) => seeded += count,
+                Err(source) => warn!(
+                    ?source,
+                    guild = guild.id.get(),
+                    "failed to fetch guild channels, skipping it"
+                ),
+            }
+        }
+        if guilds.len() < GUILD_PAGE_SIZE {
+            return Ok(seeded);
+        }
+        after = guilds.last().map(|guild| guild.id);
+    }
+}
+
+/// Classify all of a guild's channels with a single request, returning how many were cached.
+///
+/// Ratelimited requests are retried a few times, since this mostly runs in bulk.
+pub async fn seed_guild(state: &AppState, guild_id: Id<GuildMarker>) -> Result<usize, Error> {
+    let channels = crate::retry::retry(state, || {
+        state.timed("guild_channels", state.client.guild_channels(guild_id))
+    })
+    .await?;
+    let mut forums = state.forums.write();
+    forums.extend(
+        channels
+            .iter()
+            .map(|channel| (channel.id, ParentChannel::new(&state.config, channel))),
+    );
+    Ok(channels.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::{self, MockApi, FORUM, GUILD};
+
+    #[tokio::test(start_paused = true)]
+    async fn preseed_gives_up_when_ratelimited() {
+        let mock = MockApi::default();
+        mock.add_guild(Id::new(GUILD), vec![mock::forum(json!({}))]);
+        for _ in 0..5 {
+            mock.fail("current_user_guilds", 429);
+        }
+        let state = mock::state(&mock, &[("RETRY_BUDGET", "10")]);
+        preseed(&state).await;
+        assert_eq!(mock.count("current_user_guilds"), 3);
+        assert_eq!(mock.count("guild_channels"), 0);
+        assert!(state
+            .metrics
+            .render()
+            .contains("xlr_http_ratelimited_total{route=\"current_user_guilds\"} 3\n"));
+        assert_eq!(state.forums.read().len(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ratelimited_guilds_share_the_retry_budget() {
+        let mock = MockApi::default();
+        mock.add_guild(Id::new(GUILD), vec![]);
+        mock.add_guild(Id::new(5), vec![mock::forum(json!({}))]);
+        mock.fail("guild_channels", 429);
+        mock.fail("guild_channels", 429);
+        let state = mock::state(
+            &mock,
+            &[("RETRY_BUDGET", "1"), ("RETRY_REFILL_SECS", "3600")],
+        );
+        preseed(&state).await;
+        // The first guild's one retry empties the budget, so it's skipped
+        // and the second guild is fetched on its own first try
+        assert_eq!(mock.count("guild_channels"), 3);
+        assert!(state
+            .forums
+            .read()
+            .iter()
+            .any(|(id, ..)| *id == Id::new(FORUM)));
+        assert!(!state.retry_budget.try_acquire());
+    }
+}
@@ -0,0 +1,167 @@
+use std::{future::Future, time::Duration};
+
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+use crate::{config::Config, AppState, Error};
+
+/// Attempts at a request before giving up on it.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Token bucket shared by every retry in the process, so a Discord outage
+/// doesn't get worse from the bot retrying everything at once.
+#[derive(Debug)]
+pub struct RetryBudget {
+    capacity: u32,
+    /// How long it takes to earn back one retry
+    refill: Duration,
+    state: Mutex<State>,
+}
+
+/// Timed with tokio's clock, so tests can skip ahead to the next refill.
+#[derive(Debug)]
+struct State {
+    tokens: u32,
+    last_refill: Instant,
+    /// Whether exhaustion has been logged since the budget last had tokens
+    exhausted: bool,
+}
+
+impl RetryBudget {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            capacity: config.retry_budget,
+            refill: config.retry_refill,
+            state: Mutex::new(State {
+                tokens: config.retry_budget,
+                last_refill: Instant::now(),
+                exhausted: false,
+            }),
+        }
+    }
+
+    /// Take one retry from the budget, returning `false` if there are none left.
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock();
+        if !self.refill.is_zero() {
+            let earned = state.last_refill.elapsed().as_nanos() / self.refill.as_nanos();
+            if earned > 0 {
+                let earned = u32::try_from(earned).unwrap_or(u32::MAX);
+                state.tokens = state.tokens.saturating_add(earned).min(self.capacity);
+                state.last_refill += self.refill * earned;
+            }
+        }
+        if state.tokens == 0 {
+            if !state.exhausted {
+                state.exhausted = true;
+                warn!("retry budget exhausted, failing requests without retrying");
+            }
+            return false;
+        }
+        state.tokens -= 1;
+        state.exhausted = false;
+        true
+    }
+}
+
+/// Discord errors that may go away if the request is tried again.
+fn is_retryable(source: &Error) -> bool {
+    source
+        .status()
+        .is_some_and(|status| status == 429 || (500..600).contains(&status))
+}
+
+/// Run a request, retrying with backoff on ratelimits and server errors
+/// while the shared retry budget allows.
+///
+/// Any other error, or running out of attempts or budget, is returned for
+/// the caller to handle.
+pub async fn retry<T, F, Fut>(state: &AppState, mut request: F) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Err(source)
+                if is_retryable(&source)
+                    && attempt < MAX_ATTEMPTS
+                    && state.retry_budget.try_acquire() =>
+            {
+                warn!(
+                    attempt,
+                    ?delay,
+                    status = source.status(),
+                    "request failed, retrying"
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::mock::{self, MockApi};
+
+    fn budget(vars: &[(&str, &str)]) -> RetryBudget {
+        RetryBudget::new(&mock::config(vars))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn budget_runs_out() {
+        let budget = budget(&[("RETRY_BUDGET", "2"), ("RETRY_REFILL_SECS", "6")]);
+        assert!(budget.try_acquire());
+        assert!(budget.try_acquire());
+        assert!(!budget.try_acquire());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn budget_refills_up_to_capacity() {
+        let budget = budget(&[("RETRY_BUDGET", "2"), ("RETRY_REFILL_SECS", "6")]);
+        assert!(budget.try_acquire());
+        assert!(budget.try_acquire());
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert!(!budget.try_acquire());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(budget.try_acquire());
+        assert!(!budget.try_acquire());
+        tokio::time::advance(Duration::from_secs(600)).await;
+        assert!(budget.try_acquire());
+        assert!(budget.try_acquire());
+        assert!(!budget.try_acquire());
+    }
+
+    /// How many times `retry` sends a request that always fails with `status`.
+    async fn attempts(status: u16) -> u32 {
+        let state = mock::state(&MockApi::default(), &[("RETRY_BUDGET", "10")]);
+        let attempts = Cell::new(0);
+        let result: Result<(), Error> = retry(&state, || {
+            attempts.set(attempts.get() + 1);
+            async move { Err(Error::MockStatus(status)) }
+        })
+        .await;
+        assert!(result.is_err());
+        attempts.get()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn server_errors_are_retried() {
+        assert_eq!(attempts(500).await, MAX_ATTEMPTS);
+        assert_eq!(attempts(429).await, MAX_ATTEMPTS);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn client_errors_are_not_retried() {
+        assert_eq!(attempts(403).await, 1);
+        assert_eq!(attempts(404).await, 1);
+    }
+}
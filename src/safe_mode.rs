@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::config::Config;
+
+/// Circuit breaker that stops the bot from acting after too many consecutive errors.
+#[derive(Debug)]
+pub struct SafeMode {
+    threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    consecutive_errors: u32,
+    last_error: Option<Instant>,
+    until: Option<Instant>,
+}
+
+impl SafeMode {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            threshold: config.safe_mode_threshold,
+            window: config.safe_mode_window,
+            cooldown: config.safe_mode_cooldown,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns `true` if handlers should run. Once the cooldown passes, the
+    /// next handler is let through as a test, and a single failure re-enters safe mode.
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock();
+        let Some(until) = state.until else {
+            return true;
+        };
+        if Instant::now() < until {
+            return false;
+        }
+        info!("safe mode cooldown elapsed, resuming");
+        state.until = None;
+        state.consecutive_errors = self.threshold.saturating_sub(1);
+        state.last_error = Some(Instant::now());
+        true
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock();
+        state.consecutive_errors = 0;
+        state.last_error = None;
+    }
+
+    pub fn record_error(&self) {
+        let mut state = self.state.lock();
+        let now = Instant::now();
+        if state
+            .last_error
+            .is_some_and(|last| now.duration_since(last) > self.window)
+        {
+            state.consecutive_errors = 0;
+        }
+        state.consecutive_errors += 1;
+        state.last_error = Some(now);
+        if state.consecutive_errors >= self.threshold && state.until.is_none() {
+            warn!(
+                errors = state.consecutive_errors,
+                cooldown_secs = self.cooldown.as_secs(),
+                "too many consecutive errors, entering safe mode"
+            );
+            state.until = Some(now + self.cooldown);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Saving the gateway session across restarts, so a new process can resume
+//! it and receive the events sent while it was down instead of identifying
+//! from scratch.
+//!
+//! If Discord rejects the resume, twilight falls back to a fresh identify on its own.
+
+use std::path::Path;
+
+use twilight_gateway::Session;
+
+/// Read a saved session, removing the file so it's only ever resumed once.
+pub fn load(path: &Path) -> Option<Session> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(source) if source.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(source) => {
+            warn!(?source, path = %path.display(), "failed to read saved session");
+            return None;
+        }
+    };
+    if let Err(source) = std::fs::remove_file(path) {
+        warn!(?source, path = %path.display(), "failed to remove saved session");
+    }
+    match serde_json::from_slice(&data) {
+        Ok(session) => Some(session),
+        Err(source) => {
+            warn!(?source, path = %path.display(), "failed to parse saved session");
+            None
+        }
+    }
+}
+
+pub fn save(path: &Path, session: &Session) {
+    let result = serde_json::to_vec(session)
+        .map_err(std::io::Error::from)
+        .and_then(|data| std::fs::write(path, data));
+    match result {
+        Ok(()) => info!(path = %path.display(), "saved gateway session"),
+        Err(source) => error!(?source, path = %path.display(), "failed to save gateway session"),
+    }
+}
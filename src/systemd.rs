@@ -0,0 +1,89 @@
+//! Telling systemd about the service's state with the `sd_notify` protocol.
+//!
+//! Everything here is a no-op unless XLR was built for Linux with the
+//! `systemd` feature and started by systemd with `NOTIFY_SOCKET` set.
+
+use std::time::Duration;
+
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// Send a state change like `READY=1` to systemd.
+pub fn notify(state: &str) {
+    #[cfg(all(feature = "systemd", target_os = "linux"))]
+    if let Err(source) = send(state) {
+        warn!(?source, state, "failed to notify systemd");
+    }
+    #[cfg(not(all(feature = "systemd", target_os = "linux")))]
+    let _ = state;
+}
+
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+fn send(state: &str) -> std::io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+    // A leading @ means a socket in the abstract namespace
+    if let Some(name) = path.to_str().and_then(|path| path.strip_prefix('@')) {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+    } else {
+        socket.send_to(state.as_bytes(), path)?;
+    }
+    Ok(())
+}
+
+/// Pings systemd's watchdog, if the service has one.
+///
+/// The event loop ticks this itself, so a wedged loop stops the pings and
+/// systemd restarts the service.
+#[derive(Debug)]
+pub struct Watchdog {
+    interval: Option<Interval>,
+}
+
+impl Watchdog {
+    pub fn from_env() -> Self {
+        let interval = watchdog_timeout().map(|timeout| {
+            // systemd recommends pinging at half the timeout
+            let mut interval = tokio::time::interval(timeout / 2);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+        if let Some(interval) = &interval {
+            info!(period = ?interval.period(), "pinging systemd watchdog");
+        }
+        Self { interval }
+    }
+
+    /// Wait for the next ping to be due and send it. Never completes without a watchdog.
+    pub async fn tick(&mut self) {
+        match &mut self.interval {
+            Some(interval) => {
+                interval.tick().await;
+                notify("WATCHDOG=1");
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+fn watchdog_timeout() -> Option<Duration> {
+    // The watchdog is meant for the main process only
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec)).filter(|timeout| !timeout.is_zero())
+}
+
+#[cfg(not(all(feature = "systemd", target_os = "linux")))]
+const fn watchdog_timeout() -> Option<Duration> {
+    None
+}
@@ -0,0 +1,125 @@
+//! A tiny `{placeholder}` templating pass for messages the bot posts.
+
+/// Replace each `{name}` in `template` with its value from `values`.
+///
+/// Unknown placeholders are left as they are and logged, so a typo shows
+/// up in the posted message and in the logs rather than disappearing.
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let name = &after[..end];
+        match values.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => out.push_str(value),
+            None => {
+                warn!(
+                    placeholder = name,
+                    "unknown placeholder in message template"
+                );
+                out.push_str(&rest[start..start + end + 2]);
+            }
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Escape text from users so it can't add formatting or mass mentions to a message.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '*' | '_' | '~' | '`' | '|' | '>' | '#' | '[' | ']' | '(' | ')' | '-' | '<'
+        ) {
+            out.push('\\');
+        }
+        out.push(c);
+        // A zero-width space after @ stops @everyone and @here from pinging
+        if c == '@' {
+            out.push('\u{200B}');
+        }
+    }
+    out
+}
+
+/// Cut `text` down to at most `max` characters, ending in an ellipsis if
+/// anything was cut. Returns whether it was.
+pub fn truncate(text: &mut String, max: usize) -> bool {
+    let Some((end, _)) = text.char_indices().nth(max.saturating_sub(1)) else {
+        return false;
+    };
+    if text[end..].chars().nth(1).is_none() {
+        return false;
+    }
+    text.truncate(end);
+    text.push('…');
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: &[(&str, &str)] = &[
+        ("author", "<@4>"),
+        ("thread", "Title"),
+        ("forum", "<#2>"),
+        ("guild", "1"),
+    ];
+
+    #[test]
+    fn render_replaces_each_placeholder() {
+        assert_eq!(render("{author}", VALUES), "<@4>");
+        assert_eq!(render("{thread}", VALUES), "Title");
+        assert_eq!(render("{forum}", VALUES), "<#2>");
+        assert_eq!(render("{guild}", VALUES), "1");
+        assert_eq!(
+            render("Thanks {author} for posting {thread} in {forum}!", VALUES),
+            "Thanks <@4> for posting Title in <#2>!"
+        );
+    }
+
+    #[test]
+    fn render_keeps_unknown_placeholders() {
+        assert_eq!(render("Hi {x}, {author}", VALUES), "Hi {x}, <@4>");
+    }
+
+    #[test]
+    fn render_keeps_unclosed_braces() {
+        assert_eq!(render("{author} says {hi", VALUES), "<@4> says {hi");
+        assert_eq!(render("nothing {", VALUES), "nothing {");
+    }
+
+    #[test]
+    fn escape_neutralises_mentions() {
+        assert_eq!(escape("@everyone"), "@\u{200B}everyone");
+        assert_eq!(escape("@here"), "@\u{200B}here");
+        assert_eq!(escape("<@4>"), "\\<@\u{200B}4\\>");
+    }
+
+    #[test]
+    fn escape_formatting() {
+        assert_eq!(escape("**bold** _it_"), "\\*\\*bold\\*\\* \\_it\\_");
+    }
+
+    #[test]
+    fn truncate_leaves_short_text() {
+        let mut text = "éé".to_owned();
+        assert!(!truncate(&mut text, 2));
+        assert_eq!(text, "éé");
+    }
+
+    #[test]
+    fn truncate_counts_characters() {
+        let mut text = "ééé".to_owned();
+        assert!(truncate(&mut text, 2));
+        assert_eq!(text, "é…");
+    }
+}